@@ -0,0 +1,396 @@
+use rustyline::{DefaultEditor, error::ReadlineError};
+use colored::Colorize;
+use crate::{config::Config, message::Message, llm::{query_llm, HttpClient, LlmClient}, executor::{CommandRunner, PtyRunner}, audit, interrupt, redact, safety, sandbox, snapshot, usage};
+use crate::cli::{refresh_system_prompt, trim_conversation};
+
+pub const DRY_RUN_RESULT: &str = "[Dry run] Command not executed. Assume it succeeded and continue with the next step of the plan.";
+
+/// The user's answer to a proposed command.
+pub enum Confirmation {
+    /// Run it, possibly edited.
+    Run(String),
+    Skip,
+    Abort,
+}
+
+/// Asks on the terminal whether to run a command.
+pub fn confirm_command(config: &Config, conversation: &[Message], response: &str, command: &str) -> Result<Confirmation, Box<dyn std::error::Error>> {
+    let mut rl = DefaultEditor::new()?;
+    let mut command = command.to_string();
+
+    loop {
+        let prompt = format!("Execute '{}'? [R]un / [e]dit / [s]kip / e[x]plain / [a]bort task: ", command).cyan().to_string();
+        let choice = match rl.readline(&prompt) {
+            Ok(line) => line.trim().to_lowercase(),
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return Ok(Confirmation::Abort),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        match choice.as_str() {
+            "" | "r" | "run" => return Ok(Confirmation::Run(command)),
+            "e" | "edit" => match rl.readline_with_initial(&"Edit> ".cyan().to_string(), (&command, "")) {
+                Ok(edited) if !edited.trim().is_empty() => command = edited.trim().to_string(),
+                Ok(_) => println!("{}", "Empty command, keeping the previous one.".truecolor(128, 128, 128)),
+                Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {},
+                Err(e) => return Err(Box::new(e)),
+            },
+            "s" | "skip" => return Ok(Confirmation::Skip),
+            "x" | "explain" => {
+                let mut explain_conversation = conversation.to_vec();
+                explain_conversation.push(Message { role: "assistant".to_string(), content: response.to_string() });
+                explain_conversation.push(Message {
+                    role: "user".to_string(),
+                    content: format!("Before I run it, briefly explain what `{}` does and why it is needed. Do not use any command tags in your answer.", command),
+                });
+                match query_llm(config, &explain_conversation) {
+                    Ok(explanation) => println!("{}", explanation.yellow()),
+                    // Ctrl-C while waiting for the explanation aborts like it does at the prompt
+                    Err(_) if interrupt::aborted() => return Ok(Confirmation::Abort),
+                    Err(e) => println!("{}", format!("LLM error: {}", e).red()),
+                }
+            },
+            "a" | "abort" => return Ok(Confirmation::Abort),
+            _ => println!("{}", "Unknown choice. Use r, e, s, x or a.".red()),
+        }
+    }
+}
+
+/// Why read-only mode or the path jail refuses to run a command: which of them (as recorded in
+/// the audit log) and the error for the model.
+fn refusal(config: &Config, command: &str) -> Option<(&'static str, String)> {
+    if config.read_only {
+        if let Some(reason) = safety::read_only_violation(command, &config.read_only_allowed) {
+            return Some(("read-only", format!(
+                "Error: Read-only mode: {}. Only inspection commands may run; report your findings or suggest the change to the user instead.",
+                reason
+            )));
+        }
+    }
+    if safety::path_jail_mode(config) == Some("deny") {
+        let outside = safety::jail_violations(config, command);
+        if !outside.is_empty() {
+            return Some(("path-jail", format!(
+                "Error: Path jail: the command writes or changes directory outside the allowed roots ({}): {}. The command was not executed.",
+                config.allowed_roots.join(", "),
+                outside.join(", ")
+            )));
+        }
+    }
+    None
+}
+
+/// The user's latest request, as recorded in the audit log.
+/// Commands that `--no-confirm` mode only runs when the user said `force` or `override`.
+pub fn is_dangerous(command: &str) -> bool {
+    command.contains("rm -rf") || command.contains("del /f") || command.contains("format ") || command.contains("dd if=")
+}
+
+/// Whether the user allowed dangerous commands by saying `force` or `override`. Only the user's
+/// own messages count; the system prompt and the blocking error mention the words too.
+pub fn dangerous_allowed(conversation: &[Message]) -> bool {
+    conversation.iter().any(|m| m.role == "user" && (m.content.contains("force") || m.content.contains("override")))
+}
+
+fn last_prompt(conversation: &[Message]) -> &str {
+    conversation.iter().rev().find(|m| m.role == "user").map(|m| m.content.as_str()).unwrap_or("")
+}
+
+/// Runs an AI-issued command (snapshot, execution, audit) and returns the output to send back to the model.
+pub fn run_ai_command(
+    config: &Config,
+    runner: &mut dyn CommandRunner,
+    conversation: &[Message],
+    command: &str,
+    needs_full_context: bool,
+    approval: &str,
+    silent: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let prompt = last_prompt(conversation);
+    if let Some((decision, error)) = refusal(config, command) {
+        if !silent { println!("{}", error.red()); }
+        audit::record_decision(config, prompt, command, decision);
+        return Ok(error);
+    }
+    if config.snapshots && !sandbox::is_enabled(config) && !safety::is_read_only(command) {
+        if let Err(e) = snapshot::take(config, command) {
+            if !silent { println!("{}", format!("Snapshot skipped ({}); this step cannot be undone.", e).truecolor(128, 128, 128)); }
+        }
+    }
+    let execution = runner.run(config, command, needs_full_context, silent)?;
+    audit::record_execution(config, prompt, command, approval, &execution);
+
+    let (result, redacted) = redact::redact(config, &execution.result);
+    if redacted > 0 && !silent {
+        println!("{}", format!("Redacted {} secret(s) from the output sent to the AI (see 'redactions').", redacted).truecolor(128, 128, 128));
+    }
+    Ok(result)
+}
+
+/// Where the agent is in working on a task.
+pub enum State {
+    /// Waiting for the model's next response.
+    AwaitingModel,
+    /// The model proposed a command that the user has to approve first.
+    AwaitingApproval(Step),
+    /// Running a command.
+    Executing(Step),
+    /// The model answered without a command.
+    Done,
+    /// The user stopped the task, or a limit did.
+    Aborted,
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::AwaitingModel => "awaiting-model",
+            State::AwaitingApproval(_) => "awaiting-approval",
+            State::Executing(_) => "executing",
+            State::Done => "done",
+            State::Aborted => "aborted",
+        }
+    }
+}
+
+/// A command the model asked for.
+pub struct Step {
+    /// The model's full response.
+    pub response: String,
+    /// The command as proposed by the model.
+    pub proposed: String,
+    /// The command to run, after the user's edits.
+    pub command: String,
+    pub needs_full_context: bool,
+    /// How the command was approved, for the audit log.
+    pub approval: &'static str,
+}
+
+/// Observes an agent at work and answers its questions to the user. Every method has a default:
+/// observers do nothing and questions are asked on the terminal.
+pub trait Hooks {
+    /// Called with every state the agent enters.
+    fn on_state(&mut self, _state: &State) {}
+    /// Called before each model request. An error stops the task with that message, e.g. when a
+    /// budget is used up.
+    fn before_query(&mut self, _conversation: &[Message]) -> Result<(), String> { Ok(()) }
+    /// Called with every model response.
+    fn on_response(&mut self, _response: &str) {}
+    /// Called with the result of every command, as sent back to the model.
+    fn on_output(&mut self, _step: &Step, _output: &str) {}
+    /// Asks whether to run a command that needs approval.
+    fn confirm(&mut self, config: &Config, conversation: &[Message], step: &Step) -> Result<Confirmation, Box<dyn std::error::Error>> {
+        confirm_command(config, conversation, &step.response, &step.proposed)
+    }
+}
+
+impl Hooks for () {}
+
+/// Prints the state transitions, for `debug`.
+pub struct DebugLog;
+
+impl Hooks for DebugLog {
+    fn on_state(&mut self, state: &State) {
+        println!("{}", format!("[Agent] {}", state.name()).truecolor(128, 128, 128));
+    }
+}
+
+/// Works through a task: asks the model, gets commands approved, runs them and feeds the results
+/// back until the model is done. Shared by the REPL, inline mode and plans.
+pub struct Agent<'a> {
+    config: &'a Config,
+    conversation: &'a mut Vec<Message>,
+    silent: bool,
+    /// `HttpClient` unless replaced.
+    pub llm: Box<dyn LlmClient + 'a>,
+    /// `PtyRunner` unless replaced.
+    pub runner: Box<dyn CommandRunner + 'a>,
+    /// `DebugLog` with `debug` set, otherwise the defaults.
+    pub hooks: Box<dyn Hooks + 'a>,
+    /// Model requests made for this task, capped by `max_iterations`.
+    iterations: usize,
+}
+
+impl<'a> Agent<'a> {
+    pub fn new(config: &'a Config, conversation: &'a mut Vec<Message>, silent: bool) -> Self {
+        let hooks: Box<dyn Hooks> = if config.debug { Box::new(DebugLog) } else { Box::new(()) };
+        Agent { config, conversation, silent, llm: Box::new(HttpClient), runner: Box::new(PtyRunner), hooks, iterations: 0 }
+    }
+
+    /// Runs until the task is done or aborted, and returns which of the two. `response` is a model
+    /// response to start from; without it the model is asked first. LLM errors are returned.
+    pub fn run(&mut self, response: Option<String>) -> Result<State, Box<dyn std::error::Error>> {
+        let mut pending = response;
+        let mut state = State::AwaitingModel;
+        loop {
+            self.hooks.on_state(&state);
+            state = match state {
+                State::AwaitingModel => self.await_model(pending.take())?,
+                State::AwaitingApproval(step) => self.approve(step)?,
+                State::Executing(step) => self.execute(step)?,
+                State::Done | State::Aborted => {
+                    trim_conversation(self.config, self.conversation);
+                    return Ok(state);
+                },
+            };
+        }
+    }
+
+    fn stopped(&self) -> State {
+        if !self.silent { println!("{}", "Task stopped.".yellow()); }
+        State::Aborted
+    }
+
+    fn await_model(&mut self, response: Option<String>) -> Result<State, Box<dyn std::error::Error>> {
+        let response = match response {
+            Some(response) => response,
+            None => {
+                if interrupt::aborted() {
+                    return Ok(self.stopped());
+                }
+                if self.iterations >= self.config.max_iterations {
+                    let error_msg = format!("Error: Maximum iterations ({}) reached. Task aborted to prevent infinite loop.", self.config.max_iterations);
+                    if !self.silent { println!("{}", error_msg.red()); }
+                    return Ok(State::Aborted);
+                }
+                if let Some(reason) = usage::budget_exceeded(self.config) {
+                    if !self.silent { println!("{}", format!("Error: {} Task aborted; raise `monthly_budget` to continue.", reason).red()); }
+                    return Ok(State::Aborted);
+                }
+                if let Err(reason) = self.hooks.before_query(self.conversation) {
+                    if !self.silent { println!("{}", reason.red()); }
+                    return Ok(State::Aborted);
+                }
+                self.iterations += 1;
+                trim_conversation(self.config, self.conversation);
+                match self.llm.query(self.config, self.conversation) {
+                    Ok(response) => response,
+                    Err(_) if interrupt::aborted() => return Ok(self.stopped()),
+                    Err(e) => return Err(e),
+                }
+            },
+        };
+        self.hooks.on_response(&response);
+        Ok(self.parse_response(response))
+    }
+
+    /// Picks the first command out of a model response, or finishes the task if there is none.
+    fn parse_response(&mut self, response: String) -> State {
+        let (config, silent) = (self.config, self.silent);
+        let cmd_match = response.match_indices("<cmd>").next().map(|(i, _)| (i, "</cmd>", 5));
+        let cmdctx_match = response.match_indices("<cmdctx>").next().map(|(i, _)| (i, "</cmdctx>", 8));
+
+        let (start, end_tag, open_tag_len, needs_full_context) = match (cmd_match, cmdctx_match) {
+            (Some((start, end_tag, open_len)), _) => (start, end_tag, open_len, false),
+            (_, Some((start, end_tag, open_len))) => (start, end_tag, open_len, true),
+            _ => {
+                println!("{}", response.yellow());
+                self.conversation.push(Message { role: "assistant".to_string(), content: response });
+                return State::Done;
+            }
+        };
+
+        let Some(closing_pos) = response[start..].find(end_tag) else {
+            if !silent { println!("{}", response.yellow()); }
+            self.conversation.push(Message { role: "assistant".to_string(), content: response });
+            return State::Done;
+        };
+
+        let command_start = start + open_tag_len;
+        let command_end = start + closing_pos;
+        let command = response[command_start..command_end].trim().to_string();
+
+        let is_dangerous = !config.require_confirmation && is_dangerous(&command);
+        if is_dangerous && !config.dry_run && !dangerous_allowed(self.conversation) {
+            let error_msg = format!("Error: Potentially dangerous command '{}' blocked. Use 'force' or 'override' in prompt to proceed.", command);
+            if !silent { println!("{}", error_msg.red()); }
+            audit::record_decision(config, last_prompt(self.conversation), &command, "blocked");
+            self.conversation.push(Message { role: "assistant".to_string(), content: error_msg });
+            return State::Done;
+        }
+
+        if !silent {
+            let text = response[..start].trim();
+            if !text.is_empty() {
+                println!("{}", text.yellow());
+            }
+            println!("{}", format!("[{}{}] {}",
+                if config.dry_run { "Planned" } else { "Executing" },
+                if needs_full_context { " (fo)" } else { "" },
+                command
+            ).truecolor(128, 128, 128));
+        }
+
+        // In confirm mode, writes outside the allowed roots need approval even without require_confirmation
+        let outside = if config.dry_run { Vec::new() } else { safety::jail_violations(config, &command) };
+        let jail_confirm = !outside.is_empty() && safety::path_jail_mode(config) == Some("confirm");
+        if jail_confirm {
+            println!("{}", format!("Outside the allowed roots: {}", outside.join(", ")).red());
+        }
+        // Commands that will be refused anyway are not worth confirming
+        let confirm = (config.require_confirmation || jail_confirm) && !config.dry_run && refusal(config, &command).is_none();
+        let step = Step { response, proposed: command.clone(), command, needs_full_context, approval: "auto" };
+        if confirm { State::AwaitingApproval(step) } else { State::Executing(step) }
+    }
+
+    fn approve(&mut self, mut step: Step) -> Result<State, Box<dyn std::error::Error>> {
+        match self.hooks.confirm(self.config, self.conversation, &step)? {
+            Confirmation::Run(command) => {
+                step.approval = if command != step.proposed { "edited" } else { "confirmed" };
+                step.command = command;
+                Ok(State::Executing(step))
+            },
+            Confirmation::Skip => {
+                audit::record_decision(self.config, last_prompt(self.conversation), &step.command, "skipped");
+                trim_conversation(self.config, self.conversation);
+                self.push_step(&step, "Command skipped by user.".to_string());
+                Ok(State::AwaitingModel)
+            },
+            Confirmation::Abort => {
+                audit::record_decision(self.config, last_prompt(self.conversation), &step.command, "aborted");
+                trim_conversation(self.config, self.conversation);
+                self.conversation.push(Message { role: "assistant".to_string(), content: "Command aborted by user.".to_string() });
+                Ok(State::Aborted)
+            },
+        }
+    }
+
+    fn execute(&mut self, step: Step) -> Result<State, Box<dyn std::error::Error>> {
+        let (config, silent) = (self.config, self.silent);
+        trim_conversation(config, self.conversation);
+        let output = if config.dry_run {
+            DRY_RUN_RESULT.to_string()
+        } else {
+            let cwd = std::env::current_dir().ok();
+            let output = run_ai_command(config, &mut *self.runner, self.conversation, &step.command, step.needs_full_context, step.approval, silent)?;
+            if std::env::current_dir().ok() != cwd {
+                refresh_system_prompt(config, self.conversation)?;
+            }
+            if step.command != step.proposed {
+                format!("Note: the user edited the command before running it.\n{}", output)
+            } else {
+                output
+            }
+        };
+        self.push_step(&step, output);
+        if interrupt::aborted() {
+            return Ok(self.stopped());
+        }
+
+        if !config.require_confirmation && !config.dry_run && config.cooldown > 0 {
+            if !silent { println!("{}", format!("Waiting for {} seconds due to cooldown...", config.cooldown).truecolor(128, 128, 128)); }
+            let until = std::time::Instant::now() + std::time::Duration::from_secs(config.cooldown);
+            while std::time::Instant::now() < until && !interrupt::aborted() {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+        }
+        Ok(State::AwaitingModel)
+    }
+
+    /// Records a command and its result in the conversation.
+    fn push_step(&mut self, step: &Step, output: String) {
+        self.hooks.on_output(step, &output);
+        let tag = if step.needs_full_context { "cmdctx" } else { "cmd" };
+        self.conversation.push(Message { role: "assistant".to_string(), content: format!("<{}>{}</{}>", tag, step.command, tag) });
+        self.conversation.push(Message { role: "tool".to_string(), content: output });
+    }
+}
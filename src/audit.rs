@@ -0,0 +1,243 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use colored::Colorize;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::{config::Config, executor::Execution};
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    pub user: String,
+    pub host: String,
+    pub cwd: String,
+    pub model: String,
+    pub prompt: String,
+    pub command: String,
+    pub approval: String,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    pub output_sha256: String,
+    /// False for commands that were refused, blocked, skipped or aborted; `approval` says which.
+    #[serde(default = "default_executed")]
+    pub executed: bool,
+}
+
+fn default_executed() -> bool { true }
+
+pub fn get_audit_log_path(config: &Config) -> PathBuf {
+    if !config.audit_log_path.is_empty() {
+        return PathBuf::from(&config.audit_log_path);
+    }
+    let mut data_dir = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    data_dir.push("aiosc");
+    data_dir.push("audit.jsonl");
+    data_dir
+}
+
+/// Appends one entry per executed command. Failures are reported but never stop the command flow.
+pub fn record_execution(config: &Config, prompt: &str, command: &str, approval: &str, execution: &Execution) {
+    let output_sha256 = Sha256::digest(execution.output.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    record(config, prompt, command, approval, Some(execution), output_sha256);
+}
+
+/// Appends an entry for a command that did not run: `decision` is `read-only`, `path-jail`,
+/// `blocked`, `skipped` or `aborted`.
+pub fn record_decision(config: &Config, prompt: &str, command: &str, decision: &str) {
+    record(config, prompt, command, decision, None, String::new());
+}
+
+fn record(config: &Config, prompt: &str, command: &str, approval: &str, execution: Option<&Execution>, output_sha256: String) {
+    if !config.audit_log {
+        return;
+    }
+
+    let entry = AuditEntry {
+        timestamp: utc_timestamp(SystemTime::now()),
+        user: current_user(),
+        host: hostname::get().map(|h| h.to_string_lossy().to_string()).unwrap_or_default(),
+        cwd: std::env::current_dir().map(|p| p.display().to_string()).unwrap_or_default(),
+        model: config.model.clone(),
+        prompt: prompt.to_string(),
+        command: command.to_string(),
+        approval: approval.to_string(),
+        exit_code: execution.and_then(|e| e.exit_code),
+        duration_ms: execution.map_or(0, |e| e.duration.as_millis() as u64),
+        output_sha256,
+        executed: execution.is_some(),
+    };
+
+    let path = get_audit_log_path(config);
+    if let Err(e) = append_entry(config, &path, &entry) {
+        println!("{}", format!("Failed to write audit log '{}': {}", path.display(), e).red());
+    }
+}
+
+fn append_entry(config: &Config, path: &Path, entry: &AuditEntry) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    rotate(path, config.audit_log_max_size, config.audit_log_max_files)?;
+
+    let mut file = OpenOptions::new().create(true).append(true).mode(0o600).open(path)?;
+    let line = serde_json::to_string(entry)?;
+    writeln!(file, "{}", line)
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+fn rotate(path: &Path, max_size: u64, max_files: usize) -> io::Result<()> {
+    if max_size == 0 {
+        return Ok(());
+    }
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() >= max_size => {},
+        _ => return Ok(()),
+    }
+    // At least one rotated file is kept, so rotating never throws the history away
+    for index in (1..max_files.max(1)).rev() {
+        let from = rotated_path(path, index);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, index + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| format!("uid:{}", unsafe { nix::libc::getuid() }))
+}
+
+/// Formats a time as RFC 3339 in UTC, e.g. `2025-03-01T12:30:00Z`.
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, rem / 3_600, rem % 3_600 / 60, rem % 60
+    )
+}
+
+fn read_entries(path: &Path, max_files: usize) -> Vec<AuditEntry> {
+    let mut files: Vec<PathBuf> = (1..=max_files.max(1)).rev().map(|i| rotated_path(path, i)).collect();
+    files.push(path.to_path_buf());
+
+    let mut entries = Vec::new();
+    for file in files {
+        let Ok(file) = fs::File::open(&file) else { continue };
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            if let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries
+}
+
+pub fn run_audit_command(config: &Config, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut since: Option<String> = None;
+    let mut until: Option<String> = None;
+    let mut grep: Option<String> = None;
+    let mut failed_only = false;
+    let mut json = false;
+    let mut limit = 50;
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned();
+        match args[i].as_str() {
+            "--since" => { since = value; i += 1; },
+            "--until" => { until = value; i += 1; },
+            "--grep" => { grep = value; i += 1; },
+            "--limit" => { limit = value.and_then(|v| v.parse().ok()).unwrap_or(limit); i += 1; },
+            "--failed" => failed_only = true,
+            "--json" => json = true,
+            "--help" | "-h" => {
+                println!(
+                    "{}",
+                    "Usage: aiosc audit [options]\n\
+                    \n\
+                    Options:\n\
+                    - --since <date>: Only entries at or after this UTC date/time (e.g. 2025-03-01 or 2025-03-01T12:00)\n\
+                    - --until <date>: Only entries before this UTC date/time\n\
+                    - --grep <text>: Only entries whose command or prompt contains the text\n\
+                    - --failed: Only commands that ran and exited with a non-zero or unknown code\n\
+                    - --limit <n>: Show the last n matching entries (default: 50, 0 for all)\n\
+                    - --json: Print matching entries as JSON lines\n"
+                        .blue()
+                );
+                return Ok(());
+            },
+            other => {
+                println!("{}", format!("Unknown audit option: {}", other).red());
+                return Ok(());
+            },
+        }
+        i += 1;
+    }
+
+    let path = get_audit_log_path(config);
+    if !config.audit_log {
+        println!("{}", "Audit logging is disabled (set \"audit_log\": true to enable it).".truecolor(128, 128, 128));
+    }
+
+    let entries: Vec<AuditEntry> = read_entries(&path, config.audit_log_max_files)
+        .into_iter()
+        .filter(|e| since.as_deref().is_none_or(|s| e.timestamp.as_str() >= s))
+        .filter(|e| until.as_deref().is_none_or(|u| e.timestamp.as_str() < u))
+        .filter(|e| grep.as_deref().is_none_or(|g| e.command.contains(g) || e.prompt.contains(g)))
+        .filter(|e| !failed_only || (e.executed && e.exit_code != Some(0)))
+        .collect();
+
+    if entries.is_empty() {
+        println!("{}", format!("No matching audit entries in '{}'.", path.display()).truecolor(128, 128, 128));
+        return Ok(());
+    }
+
+    let skip = if limit == 0 { 0 } else { entries.len().saturating_sub(limit) };
+    for entry in entries.iter().skip(skip) {
+        if json {
+            println!("{}", serde_json::to_string(entry)?);
+            continue;
+        }
+        let exit = match entry.exit_code {
+            _ if !entry.executed => "not run".yellow(),
+            Some(0) => "exit 0".green(),
+            Some(code) => format!("exit {}", code).red(),
+            None => "exit ?".red(),
+        };
+        println!(
+            "{} {}@{} [{}] {} {:.1}s {}",
+            entry.timestamp.truecolor(128, 128, 128),
+            entry.user,
+            entry.host,
+            entry.approval.cyan(),
+            exit,
+            entry.duration_ms as f64 / 1000.0,
+            entry.cwd.truecolor(128, 128, 128)
+        );
+        println!("  {}", entry.command.white());
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::fs::{self, OpenOptions};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::io::Write;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// What the API answered. A status of 0 means no answer at all: `body` holds the connection error.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Reply {
+    pub status: u16,
+    pub body: String,
+}
+
+/// One request/response pair, stored as a line of JSON in the cassette file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub hash: String,
+    pub request: Value,
+    pub reply: Reply,
+}
+
+struct Replay {
+    path: String,
+    entries: Vec<Entry>,
+    used: Vec<bool>,
+}
+
+static REPLAY: Mutex<Option<Replay>> = Mutex::new(None);
+
+/// Hash of the request body. The API key travels in a header and is never part of it.
+pub fn request_hash(request: &Value) -> String {
+    Sha256::digest(request.to_string().as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Empties the file, so a recording holds one session, and makes it private to the user.
+pub fn start_recording(path: &str) -> std::io::Result<()> {
+    let file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))
+}
+
+/// Appends a request and its reply to the cassette.
+pub fn record(path: &str, request: &Value, reply: &Reply) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let entry = Entry { hash: request_hash(request), request: request.clone(), reply: reply.clone() };
+    let mut file = OpenOptions::new().create(true).append(true).mode(0o600).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Reads all entries of a cassette.
+pub fn load(path: &str) -> Result<Vec<Entry>, Box<dyn std::error::Error + Send + Sync>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read cassette '{}': {}", path, e))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| serde_json::from_str(line).map_err(|e| format!("Invalid cassette '{}' line {}: {}", path, n + 1, e).into()))
+        .collect()
+}
+
+/// Serves the recorded reply for `request`: the first unused entry with the same hash, otherwise
+/// the next unused entry in recording order. Hashes differ whenever the conversation does, e.g.
+/// a system prompt naming another host or directory, so the order is the fallback.
+/// The second value tells whether the hash matched.
+pub fn replay(path: &str, request: &Value) -> Result<(Reply, bool), Box<dyn std::error::Error + Send + Sync>> {
+    let mut guard = REPLAY.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.as_ref().is_none_or(|replay| replay.path != path) {
+        let entries = load(path)?;
+        *guard = Some(Replay { path: path.to_string(), used: vec![false; entries.len()], entries });
+    }
+    let replay = guard.as_mut().expect("replay");
+
+    let hash = request_hash(request);
+    let matched = (0..replay.entries.len()).find(|&i| !replay.used[i] && replay.entries[i].hash == hash);
+    let Some(index) = matched.or_else(|| replay.used.iter().position(|used| !used)) else {
+        return Err(format!("The cassette '{}' has no more recorded responses", path).into());
+    };
+    replay.used[index] = true;
+    Ok((replay.entries[index].reply.clone(), matched.is_some()))
+}
+
+/// The message contents of a cassette's successful replies, in recording order.
+pub fn responses(path: &str) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(load(path)?
+        .into_iter()
+        .filter(|entry| (200..300).contains(&entry.reply.status))
+        .map(|entry| message_content(&entry.reply.body))
+        .collect())
+}
+
+/// The assistant message in a chat completions response body.
+pub fn message_content(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|json| json["choices"][0]["message"]["content"].as_str().map(str::to_string))
+        .unwrap_or_default()
+}
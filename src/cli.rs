@@ -1,6 +1,5 @@
-use std::io::{self, Write};
 use rustyline::{
-    Config as RustyConfig, Editor, error::ReadlineError,
+    Config as RustyConfig, Editor, DefaultEditor, error::ReadlineError,
     completion::{Completer, Pair, FilenameCompleter},
     hint::{Hinter, HistoryHinter},
     highlight::{Highlighter, MatchingBracketHighlighter, CmdKind},
@@ -8,7 +7,7 @@ use rustyline::{
     Helper, history::FileHistory
 };
 use colored::Colorize;
-use crate::{config::Config, message::Message, llm::query_llm, executor::execute_command};
+use crate::{config::{self, Config}, message::Message, agent::{self, Agent}, executor::{execute_command, env_var_allowed, PtyRunner}, audit, interrupt, redact, safety, sandbox, shell, snapshot, usage};
 
 struct AioscCompleter {
     filename_completer: FilenameCompleter,
@@ -26,8 +25,8 @@ impl Completer for AioscCompleter {
         pos: usize,
         ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Pair>)> {
-        let special_commands = vec![
-            "cd", "cmd", "exit", "help", "reset", "context"
+        let special_commands = [
+            "cd", "cmd", "exit", "help", "reset", "context", "sandbox", "undo", "plan", "redactions", "env", "usage", "profile"
         ];
 
         let (start, word) = if pos == 0 || line[..pos].ends_with(' ') {
@@ -62,7 +61,7 @@ impl Completer for AioscCompleter {
                         for entry in entries.filter_map(Result::ok) {
                             if let Ok(file_type) = entry.file_type() {
                                 if file_type.is_file() || file_type.is_symlink() {
-                                    if let Some(name) = entry.file_name().into_string().ok() {
+                                    if let Ok(name) = entry.file_name().into_string() {
                                         system_commands.push(name);
                                     }
                                 }
@@ -117,13 +116,19 @@ impl Highlighter for AioscCompleter {
 
 impl Validator for AioscCompleter {}
 
+/// Rebuilds the system prompt, e.g. after the working directory changed.
+pub fn refresh_system_prompt(config: &Config, conversation: &mut [Message]) -> Result<(), Box<dyn std::error::Error>> {
+    conversation[0] = setup_conversation(config, !config.require_confirmation)?.remove(0);
+    Ok(())
+}
+
 fn setup_conversation(config: &Config, no_confirm: bool) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
     let info = os_info::get();
     let cwd = std::env::current_dir()?;
     let os_info = format!(
-        "- OS Type: {}\n- Platform: {}\n- Release: {}\n- Hostname: {}\n- Shell: {}\n- Working Directory: {}",
+        "- OS Type: {}\n- Platform: {}\n- Release: {} {} [{}]\n- Hostname: {}\n- Shell: {}\n- Working Directory: {}",
         std::env::consts::FAMILY, std::env::consts::OS,
-        format!("{} {} [{}]", info.os_type(), info.version(), info.bitness()),
+        info.os_type(), info.version(), info.bitness(),
         hostname::get()?.to_string_lossy(), config.shell_type, cwd.display()
     );
 
@@ -137,12 +142,44 @@ fn setup_conversation(config: &Config, no_confirm: bool) -> Result<Vec<Message>,
         section
     };
 
-    let confirmation_guideline = if no_confirm {
+    let anchor_guideline = if shell::is_active(config) {
+        format!(
+            "- Commands run in one persistent shell session: `cd`, exported variables, sourced files and activated \
+             environments carry over to later commands. The current directory is: {}",
+            cwd.display()
+        )
+    } else {
+        format!(
+            "- Every command starts in a fresh shell, anchored to: {}. A leading `cd <dir>` (alone or as in `cd dir && make`) \
+             moves the anchor for later commands; other shell state does not carry over.",
+            cwd.display()
+        )
+    };
+
+    let mut confirmation_guideline = if no_confirm {
         "- Execute all commands directly without prompting the user.\n\
          - For potentially dangerous commands (e.g., deleting files), proceed only if the prompt explicitly includes 'force' or 'override'."
     } else {
         "- Warn and ask for confirmation if a command risks harm (e.g., overwriting data)."
-    };
+    }.to_string();
+    for note in [sandbox::prompt_note(config), safety::jail_prompt_note(config)].into_iter().flatten() {
+        confirmation_guideline.push('\n');
+        confirmation_guideline.push_str(&note);
+    }
+    if config.read_only {
+        confirmation_guideline.push_str(
+            "\n- READ-ONLY MODE: only commands that inspect the system (e.g., ls, cat, grep, ps, ss, journalctl, df) are allowed. \
+             Anything that changes files, services, packages or settings is rejected, as is writing output to files. \
+             Do not try to fix problems: diagnose them and tell the user which commands would fix them."
+        );
+    }
+    if config.dry_run {
+        confirmation_guideline.push_str(
+            "\n- DRY RUN: your commands will NOT be executed. Issue every command the task needs, one per response, \
+             assuming each one succeeds, so the user can review the complete plan before anything runs. \
+             When done, finish with a short numbered summary of the plan."
+        );
+    }
 
     Ok(vec![Message {
         role: "system".to_string(),
@@ -155,7 +192,7 @@ fn setup_conversation(config: &Config, no_confirm: bool) -> Result<Vec<Message>,
             **Strict Guidelines**:\n\
             - Always prefer <cmd> to minimize context size. Use <cmdctx> only when output analysis is required.\n\
             - For <cmdctx>, minimize output with shell tools (e.g., `grep`, `head`) or redirect to a file.\n\
-            - Use absolute paths in all commands. Do not use `cd`. You are anchored to: {}\n\
+            {}\n\
             - Execute one command per response in the specified format.\n\
             - Analyze <cmdctx> output in subsequent steps.\n\
             - For multi-turn tasks, ask for clarification and wait for input.\n\
@@ -170,7 +207,7 @@ fn setup_conversation(config: &Config, no_confirm: bool) -> Result<Vec<Message>,
             - check process status: Checking process...\\n<cmdctx>ps aux | grep my_app</cmdctx>\n\
             {}\n",
             os_info,
-            cwd.display(),
+            anchor_guideline,
             confirmation_guideline,
             cwd.display(),
             cwd.display(),
@@ -179,7 +216,7 @@ fn setup_conversation(config: &Config, no_confirm: bool) -> Result<Vec<Message>,
     }])
 }
 
-pub fn run_cli(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run_cli(mut config: Config) -> Result<(), Box<dyn std::error::Error>> {
     let rusty_config = RustyConfig::builder()
         .completion_type(rustyline::CompletionType::List)
         .build();
@@ -191,7 +228,7 @@ pub fn run_cli(config: Config) -> Result<(), Box<dyn std::error::Error>> {
         bracket_highlighter: MatchingBracketHighlighter::new(),
     }));
 
-    let mut conversation = setup_conversation(&config, false)?;
+    let mut conversation = setup_conversation(&config, !config.require_confirmation)?;
 
     loop {
         match rl.readline(&"aiosc> ".green()) {
@@ -208,14 +245,24 @@ pub fn run_cli(config: Config) -> Result<(), Box<dyn std::error::Error>> {
                         Available commands:\n\
                         - cd <path>: Change the working directory\n\
                         - cmd <command>: Execute a shell command directly\n\
+                        - plan <task>: Let the AI plan the task without running anything, then approve the whole plan\n\
                         - exit: Exit the program\n\
                         - reset: Clear chat history\n\
                         - context: Show current conversation context\n\
-                        - help: Show this help message\n"
+                        - redactions: Show secrets that were masked before being sent to the AI\n\
+                        - profile [name|none]: Show the profiles, or switch to another one for the rest of the session\n\
+                        - usage: Show tokens and cost of the last request, this session and this month\n\
+                        - env [KEY=VALUE | -KEY]: Show the environment policy for AI commands, or set/unset a variable for this session\n\
+                        - sandbox [off|auto|bwrap|unshare]: Show or change the sandbox for AI commands\n\
+                        - undo [n|list]: Revert file changes made by the last n AI commands (default: 1)\n\
+                        - help: Show this help message\n\
+                        \n\
+                        While the AI works: Ctrl-C interrupts the running command, a second Ctrl-C or Ctrl-] stops the task, Ctrl-Z suspends/resumes the command\n"
                             .blue()
                     ),
                     "reset" => {
                         conversation.truncate(1);
+                        shell::close();
                         println!("{}", "Chat history cleared.".yellow());
                     },
                     "context" => {
@@ -243,9 +290,9 @@ pub fn run_cli(config: Config) -> Result<(), Box<dyn std::error::Error>> {
                       let path = input[3..].trim();
                         let expanded_path = if path == "~" {
                             dirs::home_dir().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Home directory not found"))?
-                        } else if path.starts_with("~/") {
+                        } else if let Some(rest) = path.strip_prefix("~/") {
                             let mut home = dirs::home_dir().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Home directory not found"))?;
-                            home.push(&path[2..]);
+                            home.push(rest);
                             home
                         } else {
                             std::path::PathBuf::from(path)
@@ -257,6 +304,7 @@ pub fn run_cli(config: Config) -> Result<(), Box<dyn std::error::Error>> {
                                 format!("Failed to change directory to '{}': {}", expanded_path.display(), e).red()
                           );
                       } else {
+                          refresh_system_prompt(&config, &mut conversation)?;
                           println!(
                               "{}",
                               format!("Changed directory to: {}", std::env::current_dir()?.display())
@@ -264,18 +312,139 @@ pub fn run_cli(config: Config) -> Result<(), Box<dyn std::error::Error>> {
                           );
                       }
                     },
+                    "sandbox" => println!(
+                        "{}",
+                        format!("Sandbox: {}", if sandbox::is_enabled(&config) { config.sandbox.as_str() } else { "off" }).truecolor(128, 128, 128)
+                    ),
+                    input if input.starts_with("sandbox ") => {
+                        let backend = input[8..].trim();
+                        let previous = std::mem::replace(&mut config.sandbox, backend.to_string());
+                        if sandbox::is_enabled(&config) {
+                            if let Err(e) = sandbox::resolve_backend(&config) {
+                                println!("{}", format!("Cannot enable sandbox: {}", e).red());
+                                config.sandbox = previous;
+                                continue;
+                            }
+                        }
+                        refresh_system_prompt(&config, &mut conversation)?;
+                        println!("{}", format!("Sandbox: {}", backend).truecolor(128, 128, 128));
+                    },
+                    "redactions" => {
+                        let redactions = redact::redactions();
+                        if redactions.is_empty() {
+                            println!("{}", "Nothing has been redacted in this session.".truecolor(128, 128, 128));
+                        }
+                        for redaction in redactions {
+                            println!("{} {}", redaction.placeholder.cyan(), redaction.secret);
+                        }
+                    },
+                    "usage" => usage::print_report(&config),
+                    "profile" => {
+                        if config.profiles.is_empty() {
+                            println!("{}", "No profiles are configured.".truecolor(128, 128, 128));
+                        }
+                        for name in config.profiles.keys() {
+                            let marker = if *name == config.profile { "*" } else { " " };
+                            println!("{}", format!("{} {}", marker, name).truecolor(128, 128, 128));
+                        }
+                    },
+                    input if input.starts_with("profile ") => {
+                        let name = match input[8..].trim() {
+                            "none" => "",
+                            name => name,
+                        };
+                        let switched = match config::apply_profile(&config, name) {
+                            Ok(switched) => switched,
+                            Err(e) => {
+                                println!("{}", e.red());
+                                continue;
+                            }
+                        };
+                        if sandbox::is_enabled(&switched) {
+                            if let Err(e) = sandbox::resolve_backend(&switched) {
+                                println!("{}", format!("Cannot enable sandbox: {}", e).red());
+                                continue;
+                            }
+                        }
+                        config = switched;
+                        refresh_system_prompt(&config, &mut conversation)?;
+                        println!("{}", format!("Profile: {} (model {})", if name.is_empty() { "none" } else { name }, config.model).truecolor(128, 128, 128));
+                    },
+                    "env" => {
+                        let hidden: Vec<String> = std::env::vars_os()
+                            .filter_map(|(name, _)| name.into_string().ok())
+                            .filter(|name| !env_var_allowed(&config, name))
+                            .collect();
+                        println!("{}", format!("Hidden from AI commands: {}", if hidden.is_empty() { "-".to_string() } else { hidden.join(", ") }).truecolor(128, 128, 128));
+                        for (name, value) in &config.env_set {
+                            println!("{}={}", name.cyan(), value);
+                        }
+                    },
+                    input if input.starts_with("env ") => {
+                        let arg = input[4..].trim();
+                        if let Some(name) = arg.strip_prefix('-') {
+                            config.env_set.remove(name);
+                            println!("{}", format!("Removed {} from AI command environment.", name).truecolor(128, 128, 128));
+                        } else if let Some((name, value)) = arg.split_once('=') {
+                            config.env_set.insert(name.trim().to_string(), value.to_string());
+                            println!("{}", format!("AI commands will get {}.", name.trim()).truecolor(128, 128, 128));
+                        } else {
+                            println!("{}", "Usage: env [KEY=VALUE | -KEY]".red());
+                        }
+                    },
+                    "undo list" => {
+                        let steps = snapshot::list();
+                        if steps.is_empty() {
+                            println!("{}", "Nothing to undo.".truecolor(128, 128, 128));
+                        }
+                        for (step, command) in steps.iter().rev() {
+                            println!("{}", format!("[{}] {}", step, command).truecolor(128, 128, 128));
+                        }
+                    },
+                    input if input == "undo" || input.starts_with("undo ") => {
+                        let arg = input[4..].trim();
+                        let Some(count) = (if arg.is_empty() { Some(1) } else { arg.parse::<usize>().ok() }) else {
+                            println!("{}", "Usage: undo [n|list]".red());
+                            continue;
+                        };
+                        match snapshot::undo(count) {
+                            Ok(undone) if undone.is_empty() => println!("{}", "Nothing to undo.".truecolor(128, 128, 128)),
+                            Ok(undone) => {
+                                for command in &undone {
+                                    println!("{}", format!("Undone: {}", command).yellow());
+                                }
+                                conversation.push(Message {
+                                    role: "user".to_string(),
+                                    content: format!("Note: I reverted the file changes made by these commands: {}", undone.join("; ")),
+                                });
+                            },
+                            Err(e) => println!("{}", format!("Undo failed: {}", e).red()),
+                        }
+                    },
+                    input if input.starts_with("plan ") => {
+                        let _interrupt = interrupt::guard();
+                        if let Err(e) = run_plan(&config, &mut conversation, input[5..].trim()) {
+                            println!("{}", format!("LLM error: {}", e).red());
+                        }
+                    },
                     input if input.starts_with("cmd ") => {
+                        let _interrupt = interrupt::guard();
                         let command = input[4..].trim();
                         println!("{}", format!("[Direct] {}", command).truecolor(128, 128, 128));
-                        let output = execute_command(&config, command, false, true, false)?; // Pass false for silent (direct cmd always shows output)
-                        println!("{}", output.white());
+                        let cwd = std::env::current_dir().ok();
+                        let execution = execute_command(&config, command, false, true, false)?; // Pass false for silent (direct cmd always shows output)
+                        audit::record_execution(&config, "", command, "direct", &execution);
+                        if std::env::current_dir().ok() != cwd {
+                            refresh_system_prompt(&config, &mut conversation)?;
+                        }
+                        println!("{}", execution.result.white());
                     },
                     _ => {
+                        let _interrupt = interrupt::guard();
                         trim_conversation(&config, &mut conversation);
                         conversation.push(Message { role: "user".to_string(), content: input.to_string() });
-                        match query_llm(&config, &conversation) {
-                            Ok(response) => process_response(&config, &mut conversation, response, false)?,
-                            Err(e) => println!("{}", format!("LLM error: {}", e).red()),
+                        if let Err(e) = Agent::new(&config, &mut conversation, false).run(None) {
+                            println!("{}", format!("LLM error: {}", e).red());
                         }
                     }
                 }
@@ -287,37 +456,28 @@ pub fn run_cli(config: Config) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-pub fn run_non_interactive(config: Config, prompt: &str, silent: bool) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run_non_interactive(mut config: Config, prompt: &str, silent: bool) -> Result<(), Box<dyn std::error::Error>> {
+    // Snapshots are only useful while the REPL can undo them
+    config.snapshots = false;
     let mut conversation = setup_conversation(&config, !config.require_confirmation)?;
-    let mut iteration = 0;
+    let _interrupt = interrupt::guard();
 
-    trim_conversation(&config, &mut conversation);
     conversation.push(Message { role: "user".to_string(), content: prompt.to_string() });
-
-    loop {
-        if iteration >= config.max_iterations {
-            let error_msg = format!("Error: Maximum iterations ({}) reached. Task aborted to prevent infinite loop.", config.max_iterations);
-            if !silent { println!("{}", error_msg.red()); }
-            return Ok(());
-        }
-
-        match query_llm(&config, &conversation) {
-            Ok(response) => {
-                let has_commands = response.contains("<cmd>") || response.contains("<cmdctx>");
-                process_response(&config, &mut conversation, response, silent)?;
-                if !has_commands {
-                    break;
-                }
-            }
-            Err(e) => {
-                if !silent { println!("{}", format!("LLM error: {}", e).red()); }
-                return Err(e);
-            }
+    let result = Agent::new(&config, &mut conversation, silent).run(None);
+    if let Err(e) = &result {
+        if !silent { println!("{}", format!("LLM error: {}", e).red()); }
+    } else if config.dry_run && !silent {
+        let plan = planned_commands(&conversation);
+        if !plan.is_empty() {
+            print_plan(&config, &plan, agent::dangerous_allowed(&conversation));
         }
-        iteration += 1;
     }
 
-    Ok(())
+    let totals = usage::session();
+    if totals.requests > 0 && !silent {
+        println!("{}", format!("Usage: {}", totals.summary()).truecolor(128, 128, 128));
+    }
+    result.map(|_| ())
 }
 
 pub fn trim_conversation(config: &Config, conversation: &mut Vec<Message>) {
@@ -332,92 +492,126 @@ pub fn trim_conversation(config: &Config, conversation: &mut Vec<Message>) {
     }
 }
 
-pub fn process_response(config: &Config, conversation: &mut Vec<Message>, response: String, silent: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let cmd_match = response.match_indices("<cmd>").next().map(|(i, _)| (i, "</cmd>", 5));
-    let cmdctx_match = response.match_indices("<cmdctx>").next().map(|(i, _)| (i, "</cmdctx>", 8));
-    
-    let (start, end_tag, open_tag_len, needs_full_context) = match (cmd_match, cmdctx_match) {
-        (Some((start, end_tag, open_len)), _) => (start, end_tag, open_len, false),
-        (_, Some((start, end_tag, open_len))) => (start, end_tag, open_len, true),
-        _ => {
-            if !silent || (!response.contains("<cmd>") && !response.contains("<cmdctx>")) {
-                println!("{}", response.yellow());
+/// Commands recorded with a dry-run result, in order, as `(needs_full_context, command)`.
+fn planned_commands(messages: &[Message]) -> Vec<(bool, String)> {
+    messages
+        .windows(2)
+        .filter(|pair| pair[0].role == "assistant" && pair[1].role == "tool" && pair[1].content == agent::DRY_RUN_RESULT)
+        .filter_map(|pair| {
+            let content = &pair[0].content;
+            if let Some(command) = content.strip_prefix("<cmdctx>").and_then(|c| c.strip_suffix("</cmdctx>")) {
+                Some((true, command.to_string()))
+            } else {
+                content.strip_prefix("<cmd>").and_then(|c| c.strip_suffix("</cmd>")).map(|command| (false, command.to_string()))
             }
-            conversation.push(Message { role: "assistant".to_string(), content: response });
-            return Ok(());
-        }
-    };
-
-    let Some(closing_pos) = response[start..].find(end_tag) else {
-        if !silent { println!("{}", response.yellow()); }
-        conversation.push(Message { role: "assistant".to_string(), content: response });
-        return Ok(());
-    };
+        })
+        .collect()
+}
 
-    let command_start = start + open_tag_len;
-    let command_end = start + closing_pos;
-    let command = response[command_start..command_end].trim();
+fn print_plan(config: &Config, plan: &[(bool, String)], dangerous_allowed: bool) {
+    println!("{}", "--- Plan ---".yellow());
+    for (i, (_, command)) in plan.iter().enumerate() {
+        println!("{} {}", format!("{:>3}.", i + 1).cyan(), command);
+        if agent::is_dangerous(command) {
+            let note = if config.require_confirmation {
+                "potentially dangerous; you will be asked again before it runs"
+            } else if dangerous_allowed {
+                "potentially dangerous; allowed by 'force'/'override'"
+            } else {
+                "potentially dangerous; blocked without 'force' or 'override' in the task"
+            };
+            println!("{}", format!("     {}", note).red());
+        }
+        let outside = safety::jail_violations(config, command);
+        if !outside.is_empty() {
+            println!("{}", format!("     leaves the allowed roots: {}", outside.join(", ")).red());
+        }
+    }
+    println!("{}", "--- End of Plan ---".yellow());
+}
 
-    let is_dangerous = !config.require_confirmation && (
-        command.contains("rm -rf") || 
-        command.contains("del /f") || 
-        command.contains("format ") || 
-        command.contains("dd if=")
-    );
-    if is_dangerous && !conversation.iter().any(|m| m.content.contains("force") || m.content.contains("override")) {
-        let error_msg = format!("Error: Potentially dangerous command '{}' blocked. Use 'force' or 'override' in prompt to proceed.", command);
-        if !silent { println!("{}", error_msg.red()); }
-        conversation.push(Message { role: "assistant".to_string(), content: error_msg });
+/// Lets the model work through a task with execution stubbed out, then runs the resulting plan
+/// as a whole once the user approves it. Dangerous steps go through the same gate as in the
+/// normal loop, and commands the model wants to run afterwards need confirmation.
+fn run_plan(config: &Config, conversation: &mut Vec<Message>, task: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut plan_config = config.clone();
+    plan_config.dry_run = true;
+    let mut plan_conversation = setup_conversation(&plan_config, true)?;
+    plan_conversation.extend(conversation.iter().skip(1).cloned());
+    plan_conversation.push(Message { role: "user".to_string(), content: task.to_string() });
+
+    if let agent::State::Aborted = Agent::new(&plan_config, &mut plan_conversation, false).run(None)? {
         return Ok(());
     }
 
-    if !silent {
-    let text = response[..start].trim();
-        if !text.is_empty() { 
-        println!("{}", text.yellow()); 
+    let plan = planned_commands(&plan_conversation);
+    if plan.is_empty() {
+        println!("{}", "The AI did not propose any commands.".truecolor(128, 128, 128));
+        return Ok(());
     }
-        println!("{}", format!("[Executing{}] {}", 
-            if needs_full_context { " (fo)" } else { "" }, 
-            command
-        ).truecolor(128, 128, 128));
+    let task_message = Message { role: "user".to_string(), content: task.to_string() };
+    let dangerous_allowed = agent::dangerous_allowed(conversation) || agent::dangerous_allowed(std::slice::from_ref(&task_message));
+    print_plan(config, &plan, dangerous_allowed);
+
+    let mut rl = DefaultEditor::new()?;
+    let approved = match rl.readline(&"Run this plan? [y/N]: ".cyan().to_string()) {
+        Ok(answer) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => false,
+        Err(e) => return Err(Box::new(e)),
+    };
+    if !approved {
+        println!("{}", "Plan discarded.".yellow());
+        return Ok(());
     }
 
-    let should_execute = if config.require_confirmation {
-        print!("{}", format!("Execute '{}'? Press Enter to confirm, any key + Enter to abort: ", command).cyan());
-        io::stdout().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        input.trim().is_empty()
-    } else { true };
-
     trim_conversation(config, conversation);
-    if should_execute {
-        let output = execute_command(config, command, needs_full_context, false, silent)?;
-        conversation.push(Message {
-            role: "assistant".to_string(),
-            content: format!("<{}>{}</{}>", 
-                if needs_full_context { "cmdctx" } else { "cmd" }, 
-                command, 
-                if needs_full_context { "cmdctx" } else { "cmd" }
-            ),
-        });
-        conversation.push(Message { role: "tool".to_string(), content: output.clone() });
-
-        if !config.require_confirmation && config.cooldown > 0 {
-            if !silent { println!("{}", format!("Waiting for {} seconds due to cooldown...", config.cooldown).truecolor(128, 128, 128)); }
-            std::thread::sleep(std::time::Duration::from_secs(config.cooldown));
-        }
-
-        match query_llm(config, conversation) {
-            Ok(next_response) => process_response(config, conversation, next_response, silent)?,
-            Err(e) => {
-                if !silent { println!("{}", format!("LLM error: {}", e).red()); }
-                return Err(e);
+    conversation.push(task_message);
+    for (i, (needs_full_context, command)) in plan.iter().enumerate() {
+        println!("{}", format!("[Step {}/{}] {}", i + 1, plan.len(), command).truecolor(128, 128, 128));
+        if agent::is_dangerous(command) {
+            let run = if config.require_confirmation {
+                match rl.readline(&"This step is potentially dangerous. Run it? [y/N]: ".cyan().to_string()) {
+                    Ok(answer) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+                    Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => false,
+                    Err(e) => return Err(Box::new(e)),
+                }
+            } else {
+                dangerous_allowed
+            };
+            if !run {
+                let decision = if config.require_confirmation { "skipped" } else { "blocked" };
+                audit::record_decision(config, task, command, decision);
+                println!("{}", format!("Step {} was not run; the remaining steps were not run either.", i + 1).red());
+                conversation.push(Message {
+                    role: "assistant".to_string(),
+                    content: format!("Error: Potentially dangerous command '{}' was not run ({}). The rest of the plan was stopped.", command, decision),
+                });
+                break;
             }
         }
-    } else {
-        conversation.push(Message { role: "assistant".to_string(), content: "Command aborted by user.".to_string() });
+        let cwd = std::env::current_dir().ok();
+        let output = agent::run_ai_command(config, &mut PtyRunner, conversation, command, *needs_full_context, "plan", false)?;
+        if std::env::current_dir().ok() != cwd {
+            refresh_system_prompt(config, conversation)?;
+        }
+        let tag = if *needs_full_context { "cmdctx" } else { "cmd" };
+        let failed = output.starts_with("Error:");
+        conversation.push(Message { role: "assistant".to_string(), content: format!("<{}>{}</{}>", tag, command, tag) });
+        conversation.push(Message { role: "tool".to_string(), content: output });
+        if interrupt::aborted() {
+            println!("{}", "Task stopped.".yellow());
+            return Ok(());
+        }
+        if failed {
+            println!("{}", format!("Step {} failed; the remaining steps were not run.", i + 1).red());
+            break;
+        }
     }
+
+    // The model reports on the results; anything more it wants to run is outside the plan
     trim_conversation(config, conversation);
+    let mut follow_up = config.clone();
+    follow_up.require_confirmation = true;
+    Agent::new(&follow_up, conversation, false).run(None)?;
     Ok(())
-}
\ No newline at end of file
+}
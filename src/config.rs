@@ -1,32 +1,168 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use colored::Colorize;
 use json_comments::StripComments;
 use serde::{Serialize, Deserialize};
+use crate::{credentials, jsonc, safety};
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Config {
+    #[serde(default)]
     pub debug: bool,
+    #[serde(default = "default_api_addr")]
     pub api_addr: String,
+    #[serde(default)]
     pub api_key: String,
+    /// Command that prints the API key, e.g. `pass show openrouter`, used when `api_key` is empty.
+    #[serde(default)]
+    pub api_key_cmd: String,
+    /// Where the API keys saved by `aiosc init` are kept: `file` or `keyring`.
+    #[serde(default = "default_credential_store")]
+    pub credential_store: String,
+    #[serde(default = "default_model")]
     pub model: String,
+    #[serde(default = "default_show_ai_commands_output")]
     pub show_ai_commands_output: bool,
+    #[serde(default = "default_context_window_size")]
     pub context_window_size: usize,
+    #[serde(default = "default_shell_type")]
     pub shell_type: String,
+    #[serde(default = "default_require_confirmation")]
     pub require_confirmation: bool,
+    #[serde(default)]
     pub cooldown: u64,
     #[serde(default)]
     pub references: Vec<Reference>,
     #[serde(default = "default_max_iterations")]
     pub max_iterations: usize,
+    #[serde(default)]
+    pub audit_log: bool,
+    #[serde(default)]
+    pub audit_log_path: String,
+    #[serde(default = "default_audit_log_max_size")]
+    pub audit_log_max_size: u64,
+    #[serde(default = "default_audit_log_max_files")]
+    pub audit_log_max_files: usize,
+    #[serde(default = "default_sandbox")]
+    pub sandbox: String,
+    #[serde(default)]
+    pub sandbox_network: bool,
+    #[serde(default = "default_sandbox_workdir")]
+    pub sandbox_workdir: String,
+    #[serde(default = "default_snapshots")]
+    pub snapshots: bool,
+    #[serde(default = "default_snapshot_max_size")]
+    pub snapshot_max_size: u64,
+    #[serde(default = "default_redact_secrets")]
+    pub redact_secrets: bool,
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+    #[serde(default)]
+    pub env_allow: Vec<String>,
+    #[serde(default)]
+    pub env_deny: Vec<String>,
+    #[serde(default)]
+    pub env_set: BTreeMap<String, String>,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default = "default_path_jail")]
+    pub path_jail: String,
+    #[serde(default)]
+    pub allowed_roots: Vec<String>,
+    #[serde(default)]
+    pub path_jail_sandbox: bool,
+    #[serde(default)]
+    pub persistent_shell: bool,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub read_only_allowed: Vec<String>,
+    /// Extra request body fields (`temperature`, `max_tokens`, …) by model name pattern.
+    #[serde(default)]
+    pub model_params: BTreeMap<String, serde_json::Map<String, serde_json::Value>>,
+    #[serde(default)]
+    pub prices: BTreeMap<String, Price>,
+    #[serde(default)]
+    pub monthly_budget: f64,
+    #[serde(default)]
+    pub usage_path: String,
+    /// Named sets of settings that override the ones above (`--profile`).
+    #[serde(default)]
+    pub profiles: BTreeMap<String, serde_json::Map<String, serde_json::Value>>,
+    /// The active profile, empty for none.
+    #[serde(skip)]
+    pub profile: String,
+    /// What the active profile's settings were before it was applied, to switch it off again.
+    #[serde(skip)]
+    profile_base: serde_json::Map<String, serde_json::Value>,
+    #[serde(skip)]
+    pub dry_run: bool,
+    /// Cassette file that every LLM request and response is appended to (`--record`).
+    #[serde(skip)]
+    pub record: String,
+    /// Cassette file that LLM responses are served from instead of the API (`--replay`).
+    #[serde(skip)]
+    pub replay: String,
 }
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Reference {
     pub command: String,
     pub description: String,
 }
 
+/// Price of a model per million tokens, in the currency of `monthly_budget`.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Price {
+    #[serde(default)]
+    pub prompt: f64,
+    #[serde(default)]
+    pub completion: f64,
+}
+
+/// Resource limits for AI-run commands. Zero or empty means unlimited.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Limits {
+    #[serde(default)]
+    pub cpu_seconds: u64,
+    #[serde(default)]
+    pub address_space_mb: u64,
+    #[serde(default)]
+    pub open_files: u64,
+    /// RLIMIT_NPROC, which counts all processes of the user, not just the command's.
+    #[serde(default)]
+    pub processes: u64,
+    #[serde(default)]
+    pub file_size_mb: u64,
+    /// Delegated group to create command groups in. Without it aiosc moves itself into a leaf of
+    /// its own cgroup and creates them next to that.
+    #[serde(default)]
+    pub cgroup_parent: String,
+    #[serde(default)]
+    pub cgroup_memory_max: String,
+    #[serde(default)]
+    pub cgroup_cpu_max: String,
+    #[serde(default)]
+    pub cgroup_pids_max: u64,
+}
+
+fn default_api_addr() -> String { "https://openrouter.ai/api/v1".to_string() }
+fn default_credential_store() -> String { "file".to_string() }
+fn default_model() -> String { "qwen/qwen-2.5-coder-32b-instruct:free".to_string() }
+fn default_show_ai_commands_output() -> bool { true }
+fn default_context_window_size() -> usize { 32 }
+fn default_shell_type() -> String { "bash".to_string() }
+fn default_require_confirmation() -> bool { true }
 fn default_max_iterations() -> usize { 10 }
+fn default_audit_log_max_size() -> u64 { 10 * 1024 * 1024 }
+fn default_audit_log_max_files() -> usize { 5 }
+fn default_sandbox() -> String { "off".to_string() }
+fn default_sandbox_workdir() -> String { "overlay".to_string() }
+fn default_snapshots() -> bool { true }
+fn default_snapshot_max_size() -> u64 { 50 * 1024 * 1024 }
+fn default_redact_secrets() -> bool { true }
+fn default_path_jail() -> String { "off".to_string() }
 
 pub fn get_config_path() -> PathBuf {
     if let Ok(path) = std::env::var("AIOSC_CONFIG_PATH") {
@@ -38,29 +174,89 @@ pub fn get_config_path() -> PathBuf {
     config_dir
 }
 
-pub fn load_config() -> Config {
-    let mut config = Config {
+/// Where config files are looked for, lowest precedence first, as `(layer, path)`.
+pub fn config_layers() -> Vec<(&'static str, PathBuf)> {
+    let mut layers = vec![("system", PathBuf::from("/etc/aiosc/aiosc.config.json")), ("user", get_config_path())];
+    if let Some(dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(PathBuf::from)) {
+        layers.push(("binary", dir.join("aiosc.config.json")));
+    }
+    if let Some(path) = std::env::current_dir().ok().and_then(|cwd| find_project_config(&cwd)) {
+        layers.push(("project", path));
+    }
+    layers
+}
+
+/// The nearest `.aiosc.json` in `dir` or one of its parents.
+pub fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|dir| dir.join(PROJECT_CONFIG_NAME)).find(|path| path.is_file())
+}
+
+/// The built-in defaults, below all config files.
+fn default_config() -> Config {
+    Config {
         debug: false,
-        api_addr: "https://openrouter.ai/api/v1".to_string(),
+        api_addr: default_api_addr(),
         api_key: "".to_string(),
-        model: "qwen/qwen-2.5-coder-32b-instruct:free".to_string(),
-        show_ai_commands_output: true,
-        context_window_size: 32,
-        shell_type: "bash".to_string(),
-        require_confirmation: true,
+        api_key_cmd: "".to_string(),
+        credential_store: default_credential_store(),
+        model: default_model(),
+        show_ai_commands_output: default_show_ai_commands_output(),
+        context_window_size: default_context_window_size(),
+        shell_type: default_shell_type(),
+        require_confirmation: default_require_confirmation(),
         cooldown: 0,
         references: Vec::new(),
         max_iterations: default_max_iterations(),
-    };
+        audit_log: false,
+        audit_log_path: "".to_string(),
+        audit_log_max_size: default_audit_log_max_size(),
+        audit_log_max_files: default_audit_log_max_files(),
+        sandbox: default_sandbox(),
+        sandbox_network: false,
+        sandbox_workdir: default_sandbox_workdir(),
+        snapshots: default_snapshots(),
+        snapshot_max_size: default_snapshot_max_size(),
+        redact_secrets: default_redact_secrets(),
+        redact_patterns: Vec::new(),
+        env_allow: Vec::new(),
+        env_deny: Vec::new(),
+        env_set: BTreeMap::new(),
+        limits: Limits::default(),
+        path_jail: default_path_jail(),
+        allowed_roots: Vec::new(),
+        path_jail_sandbox: false,
+        persistent_shell: false,
+        read_only: false,
+        read_only_allowed: Vec::new(),
+        model_params: BTreeMap::new(),
+        prices: BTreeMap::new(),
+        monthly_budget: 0.0,
+        usage_path: "".to_string(),
+        profiles: BTreeMap::new(),
+        profile: String::new(),
+        profile_base: serde_json::Map::new(),
+        dry_run: false,
+        record: String::new(),
+        replay: String::new(),
+    }
+}
 
+pub fn load_config() -> Config {
+    load_config_with_origins().0
+}
+
+/// Loads the effective config and, for every setting that doesn't have its default value, where
+/// it came from: a config file, an environment variable or the profile.
+pub fn load_config_with_origins() -> (Config, Origins) {
+    let defaults = default_config();
     let config_path = get_config_path();
     if !config_path.exists() {
         if let Some(parent) = config_path.parent() {
             if let Err(e) = std::fs::create_dir_all(parent) {
                 println!("{}", format!("Failed to create config directory '{}': {}", parent.display(), e).red());
             } else {
-                let default_json = serde_json::to_string_pretty(&config).unwrap_or_else(|_| "{}".to_string());
-                if let Err(e) = std::fs::write(&config_path, default_json) {
+                let default_json = serde_json::to_string_pretty(&defaults).unwrap_or_else(|_| "{}".to_string());
+                if let Err(e) = credentials::write_private(&config_path, &default_json) {
                     println!("{}", format!("Failed to write default config to '{}': {}", config_path.display(), e).red());
                 } else {
                     println!("{}", format!("Created default config at '{}'", config_path.display()).green());
@@ -69,23 +265,47 @@ pub fn load_config() -> Config {
         }
     }
 
-    if let Ok(json) = std::fs::read_to_string(&config_path) {
-        let stripped = StripComments::new(json.as_bytes());
-        match serde_json::from_reader(stripped) {
-            Ok(file_config) => config = file_config,
-            Err(e) => println!(
-                "{} {} {}\n{}",
-                "Failed to parse".red(),
-                config_path.display().to_string().red(),
-                format!(": {}", e).red(),
-                "Using default config"
-            ),
+    // Files are merged setting by setting, and objects such as `limits` key by key
+    let mut settings = serde_json::to_value(&defaults).unwrap_or_default();
+    let mut origins = Origins::new();
+    for (layer, path) in config_layers() {
+        let (Some(file), _) = load_file(layer, &path, &settings) else { continue };
+        let mut merged = settings.clone();
+        merge(&mut merged, &file);
+        if let Err(e) = serde_json::from_value::<Config>(merged.clone()) {
+            println!("{}", format!("Failed to parse {}: {}\nIgnoring this file", path.display(), e).red());
+            continue;
+        }
+        for key in leaf_paths(&file) {
+            origins.insert(key, path.display().to_string());
         }
+        settings = merged;
     }
+    let mut config: Config = serde_json::from_value(settings).unwrap_or(defaults);
 
+    let before = serde_json::to_value(&config).unwrap_or_default();
+    apply_env(&mut config);
+    note_changes(&before, &config, &mut origins, |key| format!("${}", env_var_name(key)));
+    if let Ok(name) = std::env::var("AIOSC_PROFILE") {
+        let before = serde_json::to_value(&config).unwrap_or_default();
+        match apply_profile(&config, &name) {
+            Ok(profile_config) => {
+                note_changes(&before, &profile_config, &mut origins, |_| format!("profile {}", name));
+                config = profile_config;
+            }
+            Err(e) => println!("{}", format!("AIOSC_PROFILE: {}", e).red()),
+        }
+    }
+    resolve_roots(&mut config);
+    (config, origins)
+}
+/// Overrides settings with the `AIOSC_*` environment variables.
+fn apply_env(config: &mut Config) {
     if let Ok(debug) = std::env::var("AIOSC_DEBUG") { config.debug = debug.to_lowercase() == "true"; }
     if let Ok(api_addr) = std::env::var("AIOSC_API_ADDR") { config.api_addr = api_addr; }
     if let Ok(api_key) = std::env::var("AIOSC_API_KEY") { config.api_key = api_key; }
+    if let Ok(command) = std::env::var("AIOSC_API_KEY_CMD") { config.api_key_cmd = command; }
+    if let Ok(store) = std::env::var("AIOSC_CREDENTIAL_STORE") { config.credential_store = store; }
     if let Ok(model) = std::env::var("AIOSC_MODEL") { config.model = model; }
     if let Ok(show) = std::env::var("AIOSC_SHOW_AI_COMMANDS_OUTPUT") { config.show_ai_commands_output = show.to_lowercase() == "true"; }
     if let Ok(size) = std::env::var("AIOSC_CONTEXT_WINDOW_SIZE") { if let Ok(n) = size.parse() { config.context_window_size = n; } }
@@ -93,6 +313,640 @@ pub fn load_config() -> Config {
     if let Ok(confirm) = std::env::var("AIOSC_REQUIRE_CONFIRMATION") { config.require_confirmation = confirm.to_lowercase() == "true"; }
     if let Ok(cooldown) = std::env::var("AIOSC_COOLDOWN") { if let Ok(n) = cooldown.parse() { config.cooldown = n; } }
     if let Ok(max_iter) = std::env::var("AIOSC_MAX_ITERATIONS") { if let Ok(n) = max_iter.parse() { config.max_iterations = n; } }
+    if let Ok(audit) = std::env::var("AIOSC_AUDIT_LOG") { config.audit_log = audit.to_lowercase() == "true"; }
+    if let Ok(path) = std::env::var("AIOSC_AUDIT_LOG_PATH") { config.audit_log_path = path; }
+    if let Ok(sandbox) = std::env::var("AIOSC_SANDBOX") { config.sandbox = sandbox; }
+    if let Ok(network) = std::env::var("AIOSC_SANDBOX_NETWORK") { config.sandbox_network = network.to_lowercase() == "true"; }
+    if let Ok(workdir) = std::env::var("AIOSC_SANDBOX_WORKDIR") { config.sandbox_workdir = workdir; }
+    if let Ok(snapshots) = std::env::var("AIOSC_SNAPSHOTS") { config.snapshots = snapshots.to_lowercase() == "true"; }
+    if let Ok(redact) = std::env::var("AIOSC_REDACT_SECRETS") { config.redact_secrets = redact.to_lowercase() == "true"; }
+    if let Ok(jail) = std::env::var("AIOSC_PATH_JAIL") { config.path_jail = jail; }
+    if let Ok(persistent) = std::env::var("AIOSC_PERSISTENT_SHELL") { config.persistent_shell = persistent.to_lowercase() == "true"; }
+    if let Ok(read_only) = std::env::var("AIOSC_READ_ONLY") { config.read_only = read_only.to_lowercase() == "true"; }
+    if let Ok(budget) = std::env::var("AIOSC_MONTHLY_BUDGET") { if let Ok(n) = budget.parse() { config.monthly_budget = n; } }
+    if let Ok(path) = std::env::var("AIOSC_USAGE_PATH") { config.usage_path = path; }
+    if let Ok(roots) = std::env::var("AIOSC_ALLOWED_ROOTS") { config.allowed_roots = std::env::split_paths(&roots).map(|p| p.display().to_string()).collect(); }
+}
+
+/// Makes `allowed_roots` absolute. Roots are resolved against the current directory, at startup
+/// the one aiosc was started in, which is also the default root.
+fn resolve_roots(config: &mut Config) {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    if config.allowed_roots.is_empty() {
+        config.allowed_roots.push(cwd.display().to_string());
+    }
+    config.allowed_roots = config
+        .allowed_roots
+        .iter()
+        .map(|root| safety::resolve_path(&safety::expand_path(root, &cwd)).display().to_string())
+        .collect();
+}
+
+/// Switches to the profile `name`, or back to no profile if it is empty. The settings of the
+/// previous profile are reverted first; everything else, including changes made during the
+/// session, is kept.
+pub fn apply_profile(config: &Config, name: &str) -> Result<Config, String> {
+    let profile = match name {
+        "" => serde_json::Map::new(),
+        name => config.profiles.get(name).cloned().ok_or_else(|| {
+            let known: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+            format!("Unknown profile '{}' (available: {})", name, if known.is_empty() { "none".to_string() } else { known.join(", ") })
+        })?,
+    };
+
+    let mut settings = match serde_json::to_value(config) {
+        Ok(serde_json::Value::Object(settings)) => settings,
+        _ => return Err("Failed to read the current settings".to_string()),
+    };
+    for (key, value) in &config.profile_base {
+        settings.insert(key.clone(), value.clone());
+    }
+    let mut base = serde_json::Map::new();
+    for (key, value) in profile {
+        if key == "profiles" {
+            continue;
+        }
+        if let Some(previous) = settings.get_mut(&key) {
+            base.insert(key, previous.clone());
+            merge(previous, &value);
+        } else {
+            settings.insert(key, value);
+        }
+    }
+
+    let mut switched: Config = serde_json::from_value(serde_json::Value::Object(settings))
+        .map_err(|e| format!("Profile '{}' is invalid: {}", name, e))?;
+    switched.profile = name.to_string();
+    switched.profile_base = base;
+    switched.dry_run = config.dry_run;
+    switched.record = config.record.clone();
+    switched.replay = config.replay.clone();
+    resolve_roots(&mut switched);
+    Ok(switched)
+}
+
+/// Setting paths such as `model` or `limits.cpu_seconds`, mapped to where their value came from.
+pub type Origins = BTreeMap<String, String>;
+
+/// The per-project config file name, looked for in the working directory and its parents.
+pub const PROJECT_CONFIG_NAME: &str = ".aiosc.json";
+
+/// Settings a project config can't change. A repository could otherwise send the conversation
+/// and API key elsewhere, or let AI commands run unchecked for whoever works in it.
+const PROJECT_RESTRICTED: &[&str] = &[
+    "api_addr", "api_key", "api_key_cmd", "credential_store", "require_confirmation", "sandbox", "path_jail", "allowed_roots", "path_jail_sandbox",
+    "read_only", "read_only_allowed", "env_allow", "env_deny", "env_set", "redact_secrets", "redact_patterns",
+    "audit_log", "audit_log_path", "audit_log_max_size", "audit_log_max_files", "limits", "profiles", "monthly_budget", "usage_path",
+    "prices", "sandbox_network", "references", "model_params", "shell_type",
+];
+
+/// Reads a config file (comments allowed), returning it with the comments blanked out. Missing
+/// files are skipped quietly, broken ones with an error.
+fn read_config_file(path: &Path) -> Option<(serde_json::Value, String)> {
+    let json = std::fs::read_to_string(path).ok()?;
+    let mut text = String::new();
+    if let Err(e) = StripComments::new(json.as_bytes()).read_to_string(&mut text) {
+        println!("{}", format!("Failed to read {}: {}\nIgnoring this file", path.display(), e).red());
+        return None;
+    }
+    match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(value) if value.is_object() => Some((value, text)),
+        Ok(_) => {
+            println!("{}", format!("Failed to parse {}: the config must be a JSON object\nIgnoring this file", path.display()).red());
+            None
+        }
+        Err(e) => {
+            println!("{}", format!("Failed to parse {}: {}\nIgnoring this file", path.display(), e).red());
+            None
+        }
+    }
+}
+
+/// Something wrong with a setting in a config file.
+struct Problem {
+    line: Option<usize>,
+    message: String,
+    /// An invalid value rather than an unknown setting.
+    error: bool,
+}
+
+/// Checks a config file setting by setting on top of `base`. Unknown settings are reported with
+/// the closest known name, and settings with invalid values are reported and dropped, so the
+/// rest of the file still applies. Profiles are checked the same way.
+fn check_settings(text: &str, file: &mut serde_json::Map<String, serde_json::Value>, base: &serde_json::Value, parent: &[&str], problems: &mut Vec<Problem>) {
+    let known = known_keys(base);
+    for key in file.keys().cloned().collect::<Vec<_>>() {
+        let key_path: Vec<&str> = parent.iter().copied().chain([key.as_str()]).collect();
+        let line = key_line(text, &key_path);
+        let name = key_path.join(".");
+
+        if !known.contains(&key) || (key == "profiles" && !parent.is_empty()) {
+            let suggestion = closest_key(&key, &known).map(|k| format!(", did you mean '{}'?", k)).unwrap_or_default();
+            problems.push(Problem { line, message: format!("unknown setting '{}'{}", name, suggestion), error: false });
+            file.remove(&key);
+            continue;
+        }
+
+        // Keys inside objects with a fixed set of fields
+        let nested_known = match key.as_str() {
+            "limits" => Some(known_keys(&serde_json::to_value(Limits::default()).unwrap_or_default())),
+            _ => None,
+        };
+        if let (Some(nested_known), Some(serde_json::Value::Object(nested))) = (nested_known, file.get_mut(&key)) {
+            check_nested_keys(text, nested, &key_path, &nested_known, problems);
+        }
+        if let Some(serde_json::Value::Object(prices)) = file.get_mut("prices").filter(|_| key == "prices") {
+            let price_known = known_keys(&serde_json::to_value(Price::default()).unwrap_or_default());
+            for (model, price) in prices.iter_mut() {
+                if let serde_json::Value::Object(price) = price {
+                    let price_path: Vec<&str> = key_path.iter().copied().chain([model.as_str()]).collect();
+                    check_nested_keys(text, price, &price_path, &price_known, problems);
+                }
+            }
+        }
+        if let Some(serde_json::Value::Object(profiles)) = file.get_mut("profiles").filter(|_| key == "profiles") {
+            for (profile, settings) in profiles.iter_mut() {
+                if let serde_json::Value::Object(settings) = settings {
+                    let profile_path: Vec<&str> = key_path.iter().copied().chain([profile.as_str()]).collect();
+                    check_settings(text, settings, base, &profile_path, problems);
+                }
+            }
+        }
+
+        let mut candidate = base.clone();
+        merge(&mut candidate, &serde_json::json!({ key.as_str(): file[&key] }));
+        let checked = serde_json::from_value::<Config>(candidate)
+            .map_err(|e| e.to_string())
+            .and_then(|config| validate(&key, &config));
+        if let Err(e) = checked {
+            problems.push(Problem { line, message: format!("invalid value for '{}': {}; ignoring it", name, e), error: true });
+            file.remove(&key);
+        }
+    }
+}
+
+fn check_nested_keys(text: &str, object: &mut serde_json::Map<String, serde_json::Value>, parent: &[&str], known: &[String], problems: &mut Vec<Problem>) {
+    for key in object.keys().cloned().collect::<Vec<_>>() {
+        if known.contains(&key) {
+            continue;
+        }
+        let key_path: Vec<&str> = parent.iter().copied().chain([key.as_str()]).collect();
+        let line = key_line(text, &key_path);
+        let suggestion = closest_key(&key, known).map(|k| format!(", did you mean '{}'?", k)).unwrap_or_default();
+        problems.push(Problem { line, message: format!("unknown setting '{}'{}", key_path.join("."), suggestion), error: false });
+        object.remove(&key);
+    }
+}
+
+fn known_keys(defaults: &serde_json::Value) -> Vec<String> {
+    defaults.as_object().map(|map| map.keys().cloned().collect()).unwrap_or_default()
+}
+
+/// Checks values that parse but that aiosc can't use.
+fn validate(key: &str, config: &Config) -> Result<(), String> {
+    let one_of = |value: &str, accepted: &[&str], shown: &[&str]| {
+        if accepted.contains(&value.to_lowercase().as_str()) {
+            Ok(())
+        } else {
+            Err(format!("'{}' is not one of {}", value, shown.join(", ")))
+        }
+    };
+    match key {
+        "api_addr" if !config.api_addr.starts_with("http://") && !config.api_addr.starts_with("https://") => {
+            Err(format!("'{}' is not an http:// or https:// URL", config.api_addr))
+        }
+        "context_window_size" if config.context_window_size == 0 => Err("must be at least 1".to_string()),
+        "max_iterations" if config.max_iterations == 0 => Err("must be at least 1".to_string()),
+        "audit_log_max_files" if config.audit_log_max_files == 0 => Err("must be at least 1".to_string()),
+        "sandbox" => one_of(
+            &config.sandbox,
+            &["", "off", "false", "none", "auto", "on", "true", "bwrap", "bubblewrap", "unshare"],
+            &["off", "auto", "bwrap", "unshare"],
+        ),
+        "credential_store" => one_of(&config.credential_store, &["file", "keyring"], &["file", "keyring"]),
+        "sandbox_workdir" => one_of(&config.sandbox_workdir, &["overlay", "tmpfs", "readonly", "ro"], &["overlay", "tmpfs", "readonly"]),
+        "path_jail" => one_of(&config.path_jail, &["", "off", "false", "deny", "on", "true", "confirm"], &["off", "deny", "confirm"]),
+        "redact_patterns" => config
+            .redact_patterns
+            .iter()
+            .try_for_each(|pattern| regex::Regex::new(pattern).map(|_| ()).map_err(|e| format!("pattern '{}': {}", pattern, e.to_string().lines().last().unwrap_or("").trim_start_matches("error: ")))),
+        "monthly_budget" if config.monthly_budget < 0.0 => Err("must not be negative".to_string()),
+        "prices" => match config.prices.iter().find(|(_, price)| price.prompt < 0.0 || price.completion < 0.0) {
+            Some((model, _)) => Err(format!("the price of '{}' is negative", model)),
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
 
-    config
-}
\ No newline at end of file
+/// The known key closest to a misspelled one, if any is close enough.
+fn closest_key<'a>(key: &str, known: &'a [String]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2.max(candidate.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Line (from 1) of the key at `path` in a JSON text, e.g. `["limits", "cpu_seconds"]`.
+fn key_line(text: &str, path: &[&str]) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+    let (mut depth, mut matched, mut line) = (0usize, 0usize, 1usize);
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                if depth < matched {
+                    return None;
+                }
+            }
+            '"' => {
+                let mut end = start + 1;
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => { chars.next(); }
+                        '"' => { end = i; break; }
+                        _ => {}
+                    }
+                }
+                let is_key = text.get(end + 1..).unwrap_or("").trim_start().starts_with(':');
+                if is_key && depth == matched + 1 && text[start + 1..end] == *path[matched] {
+                    matched += 1;
+                    if matched == path.len() {
+                        return Some(line);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Reads and checks one config file on top of `base`, printing what is wrong with it. Returns
+/// the usable settings, if the file exists and is valid JSON, and the number of problems.
+fn load_file(layer: &str, path: &Path, base: &serde_json::Value) -> (Option<serde_json::Value>, usize) {
+    if !path.exists() {
+        return (None, 0);
+    }
+    let Some((mut file, text)) = read_config_file(path) else { return (None, 1) };
+    if holds_api_key(&file) {
+        credentials::warn_if_readable(path, "holds an API key (better kept in the keyring or the credentials file, see `aiosc init`)");
+    }
+    let mut problems = Vec::new();
+    if let Some(settings) = file.as_object_mut() {
+        check_settings(&text, settings, base, &[], &mut problems);
+    }
+    problems.sort_by_key(|problem| problem.line);
+    for problem in &problems {
+        let location = match problem.line {
+            Some(line) => format!("{}:{}", path.display(), line),
+            None => path.display().to_string(),
+        };
+        let message = format!("{}: {}", location, problem.message);
+        println!("{}", if problem.error { message.red() } else { message.yellow() });
+    }
+    let restricted = if layer == "project" { restrict_project_config(path, &mut file) } else { 0 };
+    (Some(file), problems.len() + restricted)
+}
+
+/// Whether a config file sets `api_key`, at the top or in a profile.
+fn holds_api_key(file: &serde_json::Value) -> bool {
+    let has_key = |settings: &serde_json::Value| settings["api_key"].as_str().is_some_and(|key| !key.is_empty());
+    has_key(file) || file["profiles"].as_object().is_some_and(|profiles| profiles.values().any(has_key))
+}
+
+/// Drops the settings a project config can't change. Returns how many there were.
+fn restrict_project_config(path: &Path, file: &mut serde_json::Value) -> usize {
+    let Some(settings) = file.as_object_mut() else { return 0 };
+    let ignored: Vec<String> = PROJECT_RESTRICTED.iter().filter(|key| settings.remove(**key).is_some()).map(|key| key.to_string()).collect();
+    if !ignored.is_empty() {
+        println!(
+            "{}",
+            format!("Ignoring {} in project config {}; set them in your own config instead.", ignored.join(", "), path.display()).yellow()
+        );
+    }
+    ignored.len()
+}
+
+/// Merges `overlay` into `base`: objects key by key, everything else replaced.
+fn merge(base: &mut serde_json::Value, overlay: &serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(key) {
+                    Some(existing) => merge(existing, value),
+                    None => { base.insert(key.clone(), value.clone()); }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+/// Dotted paths of the values in `value`, descending into non-empty objects.
+fn leaf_paths(value: &serde_json::Value) -> Vec<String> {
+    let mut paths = Vec::new();
+    collect_leaves(value, "", &mut |path, _| paths.push(path.to_string()));
+    paths
+}
+
+fn collect_leaves(value: &serde_json::Value, prefix: &str, visit: &mut dyn FnMut(&str, &serde_json::Value)) {
+    match value {
+        serde_json::Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                collect_leaves(value, &path, visit);
+            }
+        }
+        value => visit(prefix, value),
+    }
+}
+
+/// Records `label` as the origin of every value that differs between `before` and `after`.
+fn note_changes(before: &serde_json::Value, after: &Config, origins: &mut Origins, label: impl Fn(&str) -> String) {
+    let mut old = BTreeMap::new();
+    collect_leaves(before, "", &mut |path, value| { old.insert(path.to_string(), value.clone()); });
+    let after = serde_json::to_value(after).unwrap_or_default();
+    collect_leaves(&after, "", &mut |path, value| {
+        if old.get(path) != Some(value) {
+            origins.insert(path.to_string(), label(path));
+        }
+    });
+}
+
+/// `model` → `AIOSC_MODEL`.
+fn env_var_name(path: &str) -> String {
+    format!("AIOSC_{}", path.split('.').next().unwrap_or(path).to_uppercase())
+}
+
+/// Hides API keys anywhere in the settings.
+fn mask_secrets(value: &mut serde_json::Value) {
+    if let serde_json::Value::Object(map) = value {
+        for (key, value) in map.iter_mut() {
+            if key == "api_key" && value.as_str().is_some_and(|key| !key.is_empty()) {
+                *value = serde_json::Value::String("****".to_string());
+            } else {
+                mask_secrets(value);
+            }
+        }
+    }
+}
+
+const CONFIG_USAGE: &str = "Usage: aiosc config <command>
+  show [--origin] [--profile <name>]   Print the effective settings as JSON, or where each came from
+  list [--origin] [--profile <name>]   Print the effective settings one per line
+  get <key> [--reveal]                 Print one setting, e.g. `limits.cpu_seconds`; API keys only with --reveal
+  set <key> <value> [--project|--system]
+  unset <key> [--project|--system]     Change a setting in the user config, or the given one
+  edit [--project|--system]            Open a config file in $VISUAL or $EDITOR
+  path                                 List the config files, lowest precedence first
+  validate                             Check all config files";
+
+/// `aiosc config …`.
+pub fn run_config_command(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((command, rest)) = args.split_first() else { return Err(CONFIG_USAGE.into()) };
+    match command.as_str() {
+        "show" | "list" => show_settings(rest, command == "list"),
+        "get" => {
+            let (key, reveal) = match rest {
+                [key] => (key, false),
+                [key, flag] | [flag, key] if flag == "--reveal" => (key, true),
+                _ => return Err(CONFIG_USAGE.into()),
+            };
+            let (config, _) = load_config_with_origins();
+            let mut settings = serde_json::to_value(&config)?;
+            if !reveal {
+                mask_secrets(&mut settings);
+            }
+            let value = setting_path(key).iter().try_fold(&settings, |value, key| value.get(key)).ok_or_else(|| format!("'{}' is not set", key))?;
+            match value {
+                serde_json::Value::String(text) => println!("{}", text),
+                value => println!("{}", serde_json::to_string_pretty(value)?),
+            }
+            Ok(())
+        }
+        "set" | "unset" => {
+            let (layer, path, rest) = target_file(rest)?;
+            match (command.as_str(), rest.as_slice()) {
+                ("set", [key, value]) => set_setting(layer, &path, key, value),
+                ("unset", [key]) => unset_setting(&path, key),
+                _ => Err(CONFIG_USAGE.into()),
+            }
+        }
+        "edit" => {
+            let (layer, path, rest) = target_file(rest)?;
+            if !rest.is_empty() {
+                return Err(CONFIG_USAGE.into());
+            }
+            edit_file(layer, &path)
+        }
+        "path" => {
+            let mut layers = config_layers();
+            if !layers.iter().any(|(layer, _)| *layer == "project") {
+                layers.push(("project", std::env::current_dir()?.join(PROJECT_CONFIG_NAME)));
+            }
+            for (layer, path) in layers {
+                let state = if path.is_file() { "" } else { " (not found)" };
+                println!("{:<8} {}{}", layer, path.display(), state);
+            }
+            Ok(())
+        }
+        "validate" => {
+            let defaults = serde_json::to_value(default_config())?;
+            let mut files = 0;
+            let mut problems = 0;
+            for (layer, path) in config_layers() {
+                let (file, count) = load_file(layer, &path, &defaults);
+                files += usize::from(file.is_some() || count > 0);
+                problems += count;
+            }
+            if problems > 0 {
+                return Err(format!("{} problem(s) found", problems).into());
+            }
+            println!("{}", format!("No problems found in {} config file(s).", files).green());
+            Ok(())
+        }
+        _ => Err(CONFIG_USAGE.into()),
+    }
+}
+
+/// Splits a setting name into keys. Segments with dots, such as model names, can be quoted:
+/// `prices."gpt-4.1".prompt`.
+fn setting_path(key: &str) -> Vec<String> {
+    let mut keys = vec![String::new()];
+    let mut quoted = false;
+    for c in key.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '.' if !quoted => keys.push(String::new()),
+            c => keys.last_mut().expect("key").push(c),
+        }
+    }
+    keys
+}
+
+/// The file `set`, `unset` and `edit` change: the user config unless `--project` or `--system`
+/// is given. Returns the layer, the path and the remaining arguments.
+fn target_file(args: &[String]) -> Result<(&'static str, PathBuf, Vec<String>), Box<dyn std::error::Error>> {
+    let mut target = ("user", get_config_path());
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--project" => {
+                let cwd = std::env::current_dir()?;
+                target = ("project", find_project_config(&cwd).unwrap_or_else(|| cwd.join(PROJECT_CONFIG_NAME)));
+            }
+            "--system" => target = ("system", PathBuf::from("/etc/aiosc/aiosc.config.json")),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok((target.0, target.1, rest))
+}
+
+/// Writes a config file. New user config files are only readable by their owner, since they
+/// may get an API key.
+pub fn write_config_file(path: &Path, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let written = if !path.exists() && path == get_config_path() {
+        credentials::write_private(path, text)
+    } else {
+        path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::write(path, text))
+    };
+    written.map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+fn set_setting(layer: &str, path: &Path, key: &str, raw: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let keys = setting_path(key);
+    let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let defaults = serde_json::to_value(default_config())?;
+    let known = known_keys(&defaults);
+    if !known.contains(&keys[0]) {
+        let suggestion = closest_key(&keys[0], &known).map(|k| format!(", did you mean '{}'?", k)).unwrap_or_default();
+        return Err(format!("Unknown setting '{}'{}", keys[0], suggestion).into());
+    }
+    if layer == "project" && PROJECT_RESTRICTED.contains(&keys[0].as_str()) {
+        return Err(format!("'{}' can't be set in a project config", keys[0]).into());
+    }
+
+    // Text settings take the value as it is, others as JSON, so `set model 4o` stays a string
+    let is_text = key_refs.iter().try_fold(&defaults, |value, key| value.get(key)).is_some_and(serde_json::Value::is_string);
+    let value = if is_text { serde_json::Value::String(raw.to_string()) } else {
+        serde_json::from_str(raw).unwrap_or_else(|_| serde_json::Value::String(raw.to_string()))
+    };
+
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    let updated = jsonc::set(&text, &key_refs, &value).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+    let mut stripped = String::new();
+    StripComments::new(updated.as_bytes()).read_to_string(&mut stripped)?;
+    let file: serde_json::Value = serde_json::from_str(&stripped)?;
+    let mut changed = serde_json::Map::new();
+    changed.insert(keys[0].clone(), file[&keys[0]].clone());
+    let mut problems = Vec::new();
+    check_settings(&stripped, &mut changed, &defaults, &[], &mut problems);
+    if let Some(problem) = problems.first() {
+        return Err(problem.message.trim_end_matches("; ignoring it").to_string().into());
+    }
+
+    write_config_file(path, &updated)?;
+    println!("{}", format!("Set {} in {}", key, path.display()).truecolor(128, 128, 128));
+    Ok(())
+}
+
+fn unset_setting(path: &Path, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let keys = setting_path(key);
+    let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let updated = jsonc::remove(&text, &key_refs)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?
+        .ok_or_else(|| format!("'{}' is not set in {}", key, path.display()))?;
+    write_config_file(path, &updated)?;
+    println!("{}", format!("Removed {} from {}", key, path.display()).truecolor(128, 128, 128));
+    Ok(())
+}
+
+fn edit_file(layer: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if !path.exists() {
+        write_config_file(path, "{\n}\n")?;
+    }
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    // Through the shell, so that editors with arguments such as `code --wait` work
+    let status = std::process::Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(path).status()?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", editor, status).into());
+    }
+    let defaults = serde_json::to_value(default_config())?;
+    match load_file(layer, path, &defaults) {
+        (_, 0) => println!("{}", format!("{} has no problems.", path.display()).green()),
+        (_, problems) => println!("{}", format!("{} has {} problem(s); those settings are ignored.", path.display(), problems).yellow()),
+    }
+    Ok(())
+}
+
+/// `config show` and `config list`.
+fn show_settings(args: &[String], one_per_line: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut config, mut origins) = load_config_with_origins();
+    let mut show_origin = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--origin" => show_origin = true,
+            "--profile" => {
+                let name = args.get(i + 1).ok_or(CONFIG_USAGE)?;
+                let before = serde_json::to_value(&config)?;
+                let switched = apply_profile(&config, name)?;
+                note_changes(&before, &switched, &mut origins, |_| format!("profile {}", name));
+                config = switched;
+                i += 1;
+            }
+            other => return Err(format!("Unknown option '{}'\n{}", other, CONFIG_USAGE).into()),
+        }
+        i += 1;
+    }
+
+    let mut settings = serde_json::to_value(&config)?;
+    mask_secrets(&mut settings);
+    if !show_origin && !one_per_line {
+        println!("{}", serde_json::to_string_pretty(&settings)?);
+        return Ok(());
+    }
+
+    if show_origin {
+        println!("{}", "Config files, lowest precedence first:".truecolor(128, 128, 128));
+        for (layer, path) in config_layers() {
+            let state = if path.is_file() { "" } else { " (not found)" };
+            println!("{}", format!("  {:<8} {}{}", layer, path.display(), state).truecolor(128, 128, 128));
+        }
+    }
+    let mut lines = Vec::new();
+    collect_leaves(&settings, "", &mut |path, value| lines.push((path.to_string(), value.to_string())));
+    let width = lines.iter().map(|(path, _)| path.len()).max().unwrap_or(0);
+    for (path, value) in lines {
+        if show_origin {
+            let origin = origins.get(&path).map(String::as_str).unwrap_or("default");
+            println!("{:<width$} = {}  {}", path, value, format!("({})", origin).truecolor(128, 128, 128), width = width);
+        } else {
+            println!("{:<width$} = {}", path, value, width = width);
+        }
+    }
+    Ok(())
+}
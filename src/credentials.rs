@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use colored::Colorize;
+use crate::config::{self, Config};
+
+/// Keys already obtained, by where they came from, so `api_key_cmd` and the keyring are asked
+/// once per session.
+static CACHE: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// The `credentials` file next to the user config: API keys by API address.
+pub fn credentials_path() -> PathBuf {
+    config::get_config_path().with_file_name("credentials")
+}
+
+/// Whether keys go to the Secret Service keyring rather than the credentials file.
+pub fn uses_keyring(config: &Config) -> bool {
+    config.credential_store.eq_ignore_ascii_case("keyring")
+}
+
+/// The API key for `config.api_addr`: `api_key` if set, otherwise the output of `api_key_cmd`,
+/// otherwise the key stored for the address in the keyring or the credentials file. Empty if
+/// there is none, e.g. for local servers.
+pub fn api_key(config: &Config) -> Result<String, String> {
+    if !config.api_key.is_empty() {
+        return Ok(config.api_key.clone());
+    }
+    let source = if !config.api_key_cmd.is_empty() {
+        format!("cmd {}", config.api_key_cmd)
+    } else {
+        format!("{} {}", config.credential_store, config.api_addr)
+    };
+    if let Some(key) = CACHE.lock().unwrap_or_else(|e| e.into_inner()).get(&source) {
+        return Ok(key.clone());
+    }
+
+    let key = if !config.api_key_cmd.is_empty() {
+        run_key_command(&config.api_key_cmd)?
+    } else if uses_keyring(config) {
+        keyring_lookup(&config.api_addr)?.unwrap_or_default()
+    } else {
+        read_credentials().remove(&config.api_addr).unwrap_or_default()
+    };
+    if !key.is_empty() {
+        CACHE.lock().unwrap_or_else(|e| e.into_inner()).insert(source, key.clone());
+    }
+    Ok(key)
+}
+
+/// Runs `api_key_cmd` through the shell; the first line of its output is the key.
+fn run_key_command(command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("Failed to run api_key_cmd `{}`: {}", command, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("api_key_cmd `{}` failed ({}): {}", command, output.status, stderr.trim()));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let key = stdout.lines().next().unwrap_or("").trim().to_string();
+    if key.is_empty() {
+        return Err(format!("api_key_cmd `{}` printed no key", command));
+    }
+    Ok(key)
+}
+
+/// Whether `secret-tool` (libsecret), which talks to the Secret Service keyring, is installed.
+pub fn keyring_available() -> bool {
+    Command::new("secret-tool").stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok()
+}
+
+fn keyring_lookup(api_addr: &str) -> Result<Option<String>, String> {
+    let output = Command::new("secret-tool")
+        .args(["lookup", "service", "aiosc", "api_addr", api_addr])
+        .output()
+        .map_err(|e| format!("Failed to run secret-tool for the keyring: {}", e))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match output.status.success() {
+        true => Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string())),
+        // Nothing stored for this address
+        false if stderr.trim().is_empty() => Ok(None),
+        false => Err(format!("Failed to read the API key from the keyring: {}", stderr.trim())),
+    }
+}
+
+fn keyring_store(api_addr: &str, key: &str) -> Result<(), String> {
+    let mut child = Command::new("secret-tool")
+        .args(["store", &format!("--label=aiosc API key for {}", api_addr), "service", "aiosc", "api_addr", api_addr])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run secret-tool for the keyring: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(key.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("Failed to store the API key in the keyring: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+fn read_credentials() -> BTreeMap<String, String> {
+    let path = credentials_path();
+    let Ok(text) = fs::read_to_string(&path) else { return BTreeMap::new() };
+    warn_if_readable(&path, "holds API keys");
+    serde_json::from_str(&text).unwrap_or_else(|e| {
+        println!("{}", format!("Failed to parse {}: {}", path.display(), e).red());
+        BTreeMap::new()
+    })
+}
+
+/// Stores the key for `config.api_addr` where `credential_store` says. Returns where it went.
+pub fn store(config: &Config, key: &str) -> Result<String, String> {
+    CACHE.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    if uses_keyring(config) {
+        keyring_store(&config.api_addr, key)?;
+        return Ok("the keyring".to_string());
+    }
+    let path = credentials_path();
+    let mut keys = read_credentials();
+    keys.insert(config.api_addr.clone(), key.to_string());
+    let text = serde_json::to_string_pretty(&keys).unwrap_or_default();
+    write_private(&path, &text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path.display().to_string())
+}
+
+/// Writes a file only its owner can read, tightening the permissions of an existing one.
+pub fn write_private(path: &Path, text: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(text.as_bytes())
+}
+
+/// Warns if other users can read `path`, which `holds` something secret.
+pub fn warn_if_readable(path: &Path, holds: &str) {
+    let Ok(metadata) = fs::metadata(path) else { return };
+    if metadata.permissions().mode() & 0o044 != 0 {
+        println!(
+            "{}",
+            format!("Warning: {} {} and other users can read it; run `chmod 600 {}`.", path.display(), holds, path.display()).yellow()
+        );
+    }
+}
@@ -1,122 +1,542 @@
 use std::io::{self, Read, Write};
 use std::process::Command;
-use ptyprocess::PtyProcess;
+use std::time::{Duration, Instant};
+use ptyprocess::{PtyProcess, stream::Stream};
 use nix::pty::Winsize;
 use nix::sys::termios;
 use nix::poll::{poll, PollFd, PollFlags};
 use nix::fcntl::{fcntl, FcntlArg, F_GETFL, OFlag};
+use nix::errno::Errno;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::os::unix::io::BorrowedFd;
 use std::os::fd::AsRawFd;
 use colored::Colorize;
-use crate::config::Config;
+use crate::{config::Config, interrupt, limits, safety, sandbox, shell};
 
-pub fn execute_command(
-    config: &Config,
-    command: &str,
-    needs_full_context: bool,
-    user_command: bool,
-    silent: bool, // Added silent param
-) -> Result<String, Box<dyn std::error::Error>> {
-    let trimmed_command = command.trim();
-    if trimmed_command.starts_with("cd ") {
-        return Ok("Error: 'cd' is not allowed. Use absolute or relative paths instead.".to_string());
+pub struct Execution {
+    pub result: String,
+    pub output: String,
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+}
+
+impl Execution {
+    pub fn immediate(result: String) -> Self {
+        Execution { result, output: String::new(), exit_code: None, duration: Duration::ZERO }
     }
+}
 
-    let (shell, shell_arg) = match config.shell_type.to_lowercase().as_str() {
-        "bash" | "zsh" => (config.shell_type.as_str(), "-c"),
-        "cmd" => ("cmd.exe", "/c"),
-        "powershell" => ("powershell.exe", "-Command"),
-        _ => {
-            if !silent {
-                println!("{}", format!("Unsupported SHELL_TYPE: {}. Defaulting to 'bash'.", config.shell_type).red());
-            }
-            ("bash", "-c")
+/// Runs the commands the model asks for.
+pub trait CommandRunner {
+    fn run(&mut self, config: &Config, command: &str, needs_full_context: bool, silent: bool) -> Result<Execution, Box<dyn std::error::Error>>;
+}
+
+/// Runs commands on a pty, see `execute_command`.
+pub struct PtyRunner;
+
+impl CommandRunner for PtyRunner {
+    fn run(&mut self, config: &Config, command: &str, needs_full_context: bool, silent: bool) -> Result<Execution, Box<dyn std::error::Error>> {
+        execute_command(config, command, needs_full_context, false, silent)
+    }
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of characters.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || !name[first.len()..].ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
         }
+    }
+    true
+}
+
+/// Whether a variable of the parent environment may be passed to AI-run commands.
+/// aiosc's own `AIOSC_*` settings (including the API key) are never passed on.
+pub fn env_var_allowed(config: &Config, name: &str) -> bool {
+    if name.starts_with("AIOSC_") {
+        return false;
+    }
+    let allowed = config.env_allow.is_empty() || config.env_allow.iter().any(|p| wildcard_match(p, name));
+    allowed && !config.env_deny.iter().any(|p| wildcard_match(p, name))
+}
+
+/// Applies the environment AI-run commands get: the filtered parent environment plus `env_set`.
+pub fn apply_ai_env(config: &Config, cmd: &mut Command) {
+    for (name, _) in std::env::vars_os() {
+        if !name.to_str().is_some_and(|name| env_var_allowed(config, name)) {
+            cmd.env_remove(name);
+        }
+    }
+    cmd.envs(&config.env_set);
+}
+
+const CTRL_C: u8 = 0x03;
+const CTRL_Z: u8 = 0x1a;
+const CTRL_BRACKET: u8 = 0x1d;
+
+/// Set by the SIGWINCH handler while a command is running.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigwinch(_: nix::libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Size of the user's terminal as (columns, rows). When stdin is not a terminal, stdout or the
+/// controlling terminal is asked instead, and without any terminal it is 80x24.
+pub fn terminal_size() -> (u16, u16) {
+    let query = |fd: i32| {
+        let mut winsize = Winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        let ok = unsafe { nix::libc::ioctl(fd, nix::libc::TIOCGWINSZ, &mut winsize as *mut _) } == 0;
+        (ok && winsize.ws_col > 0 && winsize.ws_row > 0).then_some((winsize.ws_col, winsize.ws_row))
     };
+    query(0)
+        .or_else(|| query(1))
+        .or_else(|| std::fs::File::open("/dev/tty").ok().and_then(|tty| query(tty.as_raw_fd())))
+        .unwrap_or((80, 24))
+}
 
-    let mut cmd = Command::new(shell);
-    cmd.arg(shell_arg).arg(trimmed_command).envs(std::env::vars());
-    let mut process = PtyProcess::spawn(cmd)?;
+/// Sets the window size of the pty behind the master `fd`; the kernel then sends SIGWINCH to the
+/// command running in it.
+fn set_pty_size(fd: i32, (cols, rows): (u16, u16)) {
+    let winsize = Winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+    unsafe { nix::libc::ioctl(fd, nix::libc::TIOCSWINSZ, &winsize as *const _); }
+}
 
-    let mut winsize = Winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
-    unsafe { nix::libc::ioctl(0, nix::libc::TIOCGWINSZ, &mut winsize as *mut _); }
-    process.set_window_size(winsize.ws_col as u16, winsize.ws_row as u16)?;
+/// Spawns `cmd` on a new pty. The child sets the pty size before it execs, which raises SIGWINCH
+/// there; it must not run a handler inherited from us, such as rustyline's.
+pub fn spawn(cmd: Command) -> Result<PtyProcess, Box<dyn std::error::Error>> {
+    let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    let previous = unsafe { sigaction(Signal::SIGWINCH, &default) }.ok();
+    let process = PtyProcess::spawn(cmd);
+    if let Some(previous) = previous {
+        unsafe { let _ = sigaction(Signal::SIGWINCH, &previous); }
+    }
+    Ok(process?)
+}
 
-    let mut pty = process.get_pty_stream()?;
-    let pty_fd = pty.as_raw_fd();
-    let pty_borrowed_fd = unsafe { BorrowedFd::borrow_raw(pty_fd) };
+pub fn set_pty_echo(pty: &Stream, on: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let pty_borrowed_fd = unsafe { BorrowedFd::borrow_raw(pty.as_raw_fd()) };
     let mut pty_termios = termios::tcgetattr(pty_borrowed_fd)?;
-    pty_termios.local_flags |= termios::LocalFlags::ECHO;
+    pty_termios.local_flags.set(termios::LocalFlags::ECHO, on);
     termios::tcsetattr(pty_borrowed_fd, termios::SetArg::TCSANOW, &pty_termios)?;
+    Ok(())
+}
 
+/// Forwards the user's keystrokes to the pty and hands its output to `on_output` until that returns
+/// `false`, the pty closes or the user aborts the task. The terminal is in raw mode meanwhile, and
+/// resizing it resizes the pty.
+///
+/// Keys are forwarded as typed, except: a second Ctrl-C or Ctrl-] aborts (see `interrupt`), and
+/// Ctrl-Z on a stopped command resumes it.
+pub fn relay(pty: &mut Stream, on_output: impl FnMut(&str) -> bool) -> Result<(), Box<dyn std::error::Error>> {
+    let pty_fd = pty.as_raw_fd();
     let flags = fcntl(pty_fd, F_GETFL)?;
     fcntl(pty_fd, FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK))?;
 
-    let mut stdin = io::stdin();
-    let original_termios = termios::tcgetattr(&stdin)?;
-    let mut raw = original_termios.clone();
-    termios::cfmakeraw(&mut raw);
-    termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, &raw)?;
+    // Piped or redirected stdin has no terminal settings to change
+    let stdin = io::stdin();
+    let original_termios = termios::tcgetattr(&stdin).ok();
+    if let Some(original) = &original_termios {
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, &raw)?;
+    }
 
-    let mut output = String::new();
+    // No SA_RESTART, so a resize wakes up poll right away
+    RESIZED.store(false, Ordering::Relaxed);
+    let handler = SigAction::new(SigHandler::Handler(on_sigwinch), SaFlags::empty(), SigSet::empty());
+    let previous_handler = unsafe { sigaction(Signal::SIGWINCH, &handler) }.ok();
+    // The terminal may have been resized since the command was started
+    set_pty_size(pty_fd, terminal_size());
+
+    let result = relay_loop(pty, original_termios.is_some(), on_output);
+
+    if let Some(previous) = previous_handler {
+        unsafe { let _ = sigaction(Signal::SIGWINCH, &previous); }
+    }
+    if let Some(original) = &original_termios {
+        termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, original)?;
+    }
+    result
+}
+
+fn relay_loop(pty: &mut Stream, keys: bool, mut on_output: impl FnMut(&str) -> bool) -> Result<(), Box<dyn std::error::Error>> {
+    let pty_fd = pty.as_raw_fd();
+    let pty_borrowed_fd = unsafe { BorrowedFd::borrow_raw(pty_fd) };
+    let mut stdin = io::stdin();
+    let stdin_borrowed_fd = unsafe { BorrowedFd::borrow_raw(stdin.as_raw_fd()) };
     let mut buffer = [0; 4096];
     let mut input_buffer = [0; 1024];
-    let stdin_fd = stdin.as_raw_fd();
-    let stdin_borrowed_fd = unsafe { BorrowedFd::borrow_raw(stdin_fd) };
-    let mut poll_fds = [
-        PollFd::new(stdin_borrowed_fd, PollFlags::POLLIN),
-        PollFd::new(pty_borrowed_fd, PollFlags::POLLIN | PollFlags::POLLHUP),
-    ];
-
-    let mut running = true;
-    while running {
-        match poll(&mut poll_fds, 100u16)? {
-            n if n > 0 => {
-                if poll_fds[0].revents().unwrap_or(PollFlags::empty()).contains(PollFlags::POLLIN) {
+    // Stops being watched at EOF, e.g. when stdin is /dev/null
+    let mut stdin_open = true;
+    let mut interrupted = false;
+
+    loop {
+        if interrupt::aborted() {
+            return Ok(());
+        }
+        let mut poll_fds = vec![PollFd::new(pty_borrowed_fd, PollFlags::POLLIN | PollFlags::POLLHUP)];
+        if stdin_open {
+            poll_fds.push(PollFd::new(stdin_borrowed_fd, PollFlags::POLLIN));
+        }
+        match poll(&mut poll_fds, 100u16) {
+            Ok(0) | Err(Errno::EINTR) => {},
+            Ok(_) => {
+                let pty_events = poll_fds[0].revents().unwrap_or(PollFlags::empty());
+                let stdin_events = poll_fds.get(1).and_then(|fd| fd.revents()).unwrap_or(PollFlags::empty());
+                if stdin_events.intersects(PollFlags::POLLIN | PollFlags::POLLHUP) {
                     match stdin.read(&mut input_buffer) {
-                        Ok(n) if n > 0 => { pty.write_all(&input_buffer[..n])?; pty.flush()?; },
-                        Ok(_) => break,
-                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
-                        Err(e) => { termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, &original_termios)?; return Err(Box::new(e)); },
+                        Ok(n) if n > 0 => {
+                            let mut forward = Vec::with_capacity(n);
+                            for &byte in &input_buffer[..n] {
+                                match byte {
+                                    CTRL_BRACKET if keys => interrupt::abort(),
+                                    CTRL_C if keys && interrupted => interrupt::abort(),
+                                    CTRL_C if keys => {
+                                        interrupted = true;
+                                        forward.push(byte);
+                                        print!("{}", "\r\n[Ctrl-C again or Ctrl-] stops the task]\r\n".truecolor(128, 128, 128));
+                                        let _ = io::stdout().flush();
+                                    },
+                                    // The command's process group is orphaned, so the kernel would
+                                    // drop the SIGTSTP the terminal sends; stop it directly instead
+                                    CTRL_Z if keys && signal_keys_enabled(pty) => {
+                                        if foreground_stopped(pty_fd) {
+                                            signal_foreground(pty_fd, nix::libc::SIGCONT);
+                                        } else {
+                                            signal_foreground(pty_fd, nix::libc::SIGSTOP);
+                                            print!("{}", "\r\n[Command suspended: Ctrl-Z resumes it, Ctrl-] stops the task]\r\n".truecolor(128, 128, 128));
+                                            let _ = io::stdout().flush();
+                                        }
+                                    },
+                                    _ => forward.push(byte),
+                                }
+                            }
+                            if interrupt::aborted() {
+                                return Ok(());
+                            }
+                            pty.write_all(&forward)?;
+                            pty.flush()?;
+                        },
+                        Ok(_) => stdin_open = false,
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => {},
+                        Err(e) => return Err(Box::new(e)),
                     }
                 }
-                if poll_fds[1].revents().unwrap_or(PollFlags::empty()).contains(PollFlags::POLLIN) {
+                if pty_events.contains(PollFlags::POLLIN) {
                     match pty.read(&mut buffer) {
                         Ok(n) if n > 0 => {
-                            let chunk = String::from_utf8_lossy(&buffer[..n]);
-                            // Only print if not silent and (show_ai_commands_output or user_command)
-                            if !silent && (config.show_ai_commands_output || user_command) { 
-                                print!("{}", chunk); 
-                                io::stdout().flush()?; 
+                            if !on_output(&String::from_utf8_lossy(&buffer[..n])) {
+                                return Ok(());
                             }
-                            output.push_str(&chunk);
-                        }
-                        Ok(_) => running = false,
-                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
-                        Err(e) => { termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, &original_termios)?; return Err(Box::new(e)); },
+                        },
+                        Ok(_) => return Ok(()),
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => {},
+                        // Linux reports EIO once the other side of the pty is closed
+                        Err(e) if e.raw_os_error() == Some(nix::libc::EIO) => return Ok(()),
+                        Err(e) => return Err(Box::new(e)),
                     }
+                } else if pty_events.contains(PollFlags::POLLHUP) {
+                    return Ok(());
                 }
-                if poll_fds[1].revents().unwrap_or(PollFlags::empty()).contains(PollFlags::POLLHUP) { running = false; }
+            },
+            Err(_) => return Ok(()),
+        }
+        if RESIZED.swap(false, Ordering::Relaxed) {
+            set_pty_size(pty_fd, terminal_size());
+        }
+    }
+}
+
+/// The process group running in the foreground of the pty behind the master `fd`.
+fn foreground_group(fd: i32) -> Option<i32> {
+    let pgrp = unsafe { nix::libc::tcgetpgrp(fd) };
+    (pgrp > 0).then_some(pgrp)
+}
+
+fn foreground_stopped(fd: i32) -> bool {
+    let Some(pgrp) = foreground_group(fd) else { return false };
+    // The state follows the command name, which is in parentheses and may contain spaces
+    std::fs::read_to_string(format!("/proc/{}/stat", pgrp))
+        .ok()
+        .and_then(|stat| stat.rsplit_once(')').map(|(_, rest)| rest.trim_start().starts_with('T')))
+        .unwrap_or(false)
+}
+
+fn signal_foreground(fd: i32, signal: nix::libc::c_int) {
+    if let Some(pgrp) = foreground_group(fd) {
+        unsafe { nix::libc::kill(-pgrp, signal); }
+    }
+}
+
+/// Whether the command lets the terminal turn keys like Ctrl-Z into signals. Full-screen programs
+/// switch this off to read those keys themselves.
+fn signal_keys_enabled(pty: &Stream) -> bool {
+    let pty_borrowed_fd = unsafe { BorrowedFd::borrow_raw(pty.as_raw_fd()) };
+    termios::tcgetattr(pty_borrowed_fd).is_ok_and(|t| t.local_flags.contains(termios::LocalFlags::ISIG))
+}
+
+/// Kills whatever runs in the foreground of the pty, e.g. after the user aborted the task.
+pub fn kill_foreground(pty: &Stream) {
+    signal_foreground(pty.as_raw_fd(), nix::libc::SIGKILL);
+}
+
+fn aborted_result(output: &str, needs_full_context: bool) -> String {
+    if needs_full_context {
+        format!("Error: Command aborted by the user.\n{}", output)
+    } else {
+        "Error: Command aborted by the user.".to_string()
+    }
+}
+
+pub fn execute_command(
+    config: &Config,
+    command: &str,
+    needs_full_context: bool,
+    user_command: bool,
+    silent: bool, // Added silent param
+) -> Result<Execution, Box<dyn std::error::Error>> {
+    let mut trimmed_command = command.trim();
+    let persistent = !user_command && shell::is_active(config);
+    let show_output = !silent && (config.show_ai_commands_output || user_command);
+
+    // Leading `cd` steps change aiosc's own working directory, so they last beyond this command.
+    // The command runs in the new directory and aiosc follows it there once it succeeded. The
+    // persistent shell keeps track of `cd` itself.
+    let sandboxed = (sandbox::is_enabled(config) || sandbox::jail_enabled(config)) && !user_command;
+    let mut run_dir = std::env::current_dir()?;
+    let mut new_cwd = None;
+    if !persistent {
+        let (steps, rest) = split_leading_cd(trimmed_command);
+        let mut target = run_dir.clone();
+        let mut cd_error = None;
+        // After a failed `cd`, the steps chained to it with `&&` are skipped up to the next `;`
+        let mut skipping = false;
+        for (dir, separator) in &steps {
+            if !skipping {
+                let next = safety::expand_path(dir, &target);
+                let error = match std::fs::metadata(&next) {
+                    Ok(metadata) if metadata.is_dir() => None,
+                    Ok(_) => Some(format!("Error: cd: {}: Not a directory", dir)),
+                    Err(e) => Some(format!("Error: cd: {}: {}", dir, e)),
+                };
+                match error {
+                    Some(error) => (cd_error, skipping) = (Some(error), true),
+                    None => target = next,
+                }
+            }
+            if *separator == ";" {
+                skipping = false;
             }
-            0 => {},
-            _ => running = false,
+        }
+        match cd_error {
+            // The shell runs the command as written, with its own `&&` and `;` rules, and aiosc
+            // stays where it is. In the sandbox the directory may exist only in the overlay.
+            Some(_) if !rest.is_empty() => {},
+            Some(error) => {
+                std::env::set_current_dir(&target)?;
+                let result = if target != run_dir && !user_command {
+                    format!("{}\nWorking directory is now {}.", error, target.display())
+                } else {
+                    error
+                };
+                // Like the shell, the status is the last step's, so `cd bad; cd good` succeeds
+                let exit_code = Some(if skipping { 1 } else { 0 });
+                return Ok(Execution { result, output: String::new(), exit_code, duration: Duration::ZERO });
+            },
+            None if !steps.is_empty() && rest.is_empty() => {
+                std::env::set_current_dir(&target)?;
+                let result = if user_command {
+                    "".to_string()
+                } else if target != run_dir {
+                    format!("Success\nWorking directory is now {}.", target.display())
+                } else {
+                    "Success".to_string()
+                };
+                return Ok(Execution { result, output: String::new(), exit_code: Some(0), duration: Duration::ZERO });
+            },
+            _ => {
+                if target != run_dir {
+                    new_cwd = Some(target.clone());
+                }
+                run_dir = target;
+                trimmed_command = rest;
+            },
         }
     }
 
-    termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, &original_termios)?;
-    let status = process.wait()?;
-    match status {
-        ptyprocess::WaitStatus::Exited(_, code) => Ok(if code == 0 && !needs_full_context {
-            if user_command {
-                "".to_string()
-            } else {
-                "Success".to_string()
+    if persistent {
+        let started = Instant::now();
+        let run = shell::run(config, trimmed_command, show_output)?;
+        let duration = started.elapsed();
+        let mut result = if interrupt::aborted() {
+            aborted_result(&run.output, needs_full_context)
+        } else {
+            command_result(run.exit_code, None, &run.output, needs_full_context, false)
+        };
+        for note in &run.notes {
+            if !silent { println!("{}", note.truecolor(128, 128, 128)); }
+            result = format!("{}\n{}", result, note);
+        }
+        return Ok(Execution { result, output: run.output, exit_code: run.exit_code, duration });
+    }
+
+    let (shell, shell_arg) = match config.shell_type.to_lowercase().as_str() {
+        "bash" | "zsh" => (config.shell_type.as_str(), "-c"),
+        "cmd" => ("cmd.exe", "/c"),
+        "powershell" => ("powershell.exe", "-Command"),
+        _ => {
+            if !silent {
+                println!("{}", format!("Unsupported SHELL_TYPE: {}. Defaulting to 'bash'.", config.shell_type).red());
             }
-        } else if code == 0 && needs_full_context {
-            output
+            ("bash", "-c")
+        }
+    };
+
+    let mut cmd = if sandboxed {
+        let built = if sandbox::jail_enabled(config) {
+            sandbox::build_jail_command(config, &run_dir, shell, shell_arg, trimmed_command)
         } else {
-            format!("Error: Exit code {}\n{}", code, output)
-        }),
-        _ => Ok(format!("Error: Process terminated abnormally\n{}", output)),
+            sandbox::build_command(config, &run_dir, shell, shell_arg, trimmed_command)
+        };
+        match built {
+            Ok(cmd) => cmd,
+            Err(e) => return Ok(Execution::immediate(format!("Error: {}. The command was not executed.", e))),
+        }
+    } else {
+        let mut cmd = Command::new(shell);
+        cmd.arg(shell_arg).arg(trimmed_command).current_dir(&run_dir);
+        cmd
+    };
+    if user_command {
+        cmd.envs(std::env::vars());
+    } else {
+        apply_ai_env(config, &mut cmd);
+    }
+    let cgroup = if user_command {
+        None
+    } else {
+        limits::apply_rlimits(&mut cmd, &config.limits);
+        match limits::Cgroup::create(&config.limits) {
+            Ok(cgroup) => cgroup,
+            Err(e) => return Ok(Execution::immediate(format!("Error: cgroup limits could not be applied: {}. The command was not executed.", e))),
+        }
+    };
+    if let Some(cgroup) = &cgroup {
+        cgroup.attach(&mut cmd);
+    }
+    let started = Instant::now();
+    let cpu_before = limits::children_cpu_seconds();
+    let mut process = spawn(cmd)?;
+    let (cols, rows) = terminal_size();
+    process.set_window_size(cols, rows)?;
+
+    let mut pty = process.get_pty_stream()?;
+    set_pty_echo(&pty, true)?;
+
+    let mut output = String::new();
+    relay(&mut pty, |chunk| {
+        if show_output {
+            print!("{}", chunk);
+            let _ = io::stdout().flush();
+        }
+        output.push_str(chunk);
+        true
+    })?;
+
+    if interrupt::aborted() {
+        kill_foreground(&pty);
+        unsafe { nix::libc::kill(-process.pid().as_raw(), nix::libc::SIGKILL); }
+        let _ = process.wait();
+        let result = aborted_result(&output, needs_full_context);
+        return Ok(Execution { result, output, exit_code: None, duration: started.elapsed() });
+    }
+
+    let status = process.wait()?;
+    let duration = started.elapsed();
+    let (exit_code, signal) = match status {
+        ptyprocess::WaitStatus::Exited(_, code) => (Some(code), None),
+        ptyprocess::WaitStatus::Signaled(_, signal, _) => (None, Some(signal.as_str())),
+        _ => (None, None),
+    };
+    let mut result = command_result(exit_code, signal, &output, needs_full_context, user_command);
+    if !user_command {
+        // The shell reports a child killed by a signal as exit code 128 + signal
+        let signal = match exit_code {
+            Some(code) if code > 128 => nix::sys::signal::Signal::try_from(code - 128).ok().map(|s| s.as_str()),
+            _ => signal,
+        };
+        let mut events = cgroup.as_ref().map(|c| c.events()).unwrap_or_default();
+        events.cpu_seconds = events.cpu_seconds.max(limits::children_cpu_seconds() - cpu_before);
+        if let Some(error) = limits::describe_limit_hit(&config.limits, signal, &output, &events) {
+            if !silent { println!("{}", error.red()); }
+            result = format!("{}\n{}", error, result);
+        }
+    }
+    if sandboxed && exit_code != Some(0) {
+        if let Some(note) = sandbox::explain_denial(config, &output) {
+            if !silent { println!("{}", note.truecolor(128, 128, 128)); }
+            result = format!("{}\n{}", result, note);
+        }
+    }
+    if let Some(dir) = new_cwd.filter(|_| exit_code == Some(0)) {
+        std::env::set_current_dir(&dir)?;
+        if !user_command {
+            result = format!("{}\nWorking directory is now {}.", result, dir.display());
+        }
+    }
+    Ok(Execution { result, output, exit_code, duration })
+}
+
+/// Splits leading `cd <dir>` steps off a command line, each with the separator after it (`&&`,
+/// `;` or nothing): `cd src && make` gives `([("src", "&&")], "make")`. Stops at the first step
+/// that is more than `cd` with a single literal (or quoted) directory.
+fn split_leading_cd(command: &str) -> (Vec<(String, &'static str)>, &str) {
+    let mut dirs = Vec::new();
+    let mut rest = command.trim();
+    while let Some(after) = rest.strip_prefix("cd") {
+        let end = [after.find("&&"), after.find(';')].into_iter().flatten().min();
+        let (segment, separator, remainder) = match end {
+            Some(i) if after[i..].starts_with("&&") => (&after[..i], "&&", &after[i + 2..]),
+            Some(i) => (&after[..i], ";", &after[i + 1..]),
+            None => (after, "", ""),
+        };
+        if !segment.is_empty() && !segment.starts_with(char::is_whitespace) {
+            break;
+        }
+        let word = segment.trim();
+        let unquoted = match word.chars().next() {
+            Some(q @ ('\'' | '"')) if word.len() >= 2 && word.ends_with(q) => word[1..word.len() - 1].to_string(),
+            _ if word.contains(|c: char| c.is_whitespace() || "|<>()`'\"\\*?[{".contains(c)) || word == "-" => break,
+            _ if word.is_empty() => "~".to_string(),
+            _ => word.to_string(),
+        };
+        let Some(dir) = safety::expand_vars(&unquoted) else { break };
+        dirs.push((dir, separator));
+        rest = remainder.trim();
+    }
+    (dirs, rest)
+}
+
+/// What the model (or, for direct commands, the user) is told about a finished command.
+pub fn command_result(exit_code: Option<i32>, signal: Option<&str>, output: &str, needs_full_context: bool, user_command: bool) -> String {
+    match (exit_code, signal) {
+        (Some(0), _) if needs_full_context => output.to_string(),
+        (Some(0), _) if user_command => "".to_string(),
+        (Some(0), _) => "Success".to_string(),
+        (Some(code), _) => format!("Error: Exit code {}\n{}", code, output),
+        (None, Some(signal)) => format!("Error: Process killed by {}\n{}", signal, output),
+        (None, None) => format!("Error: Process terminated abnormally\n{}", output),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,175 @@
+use std::io::{self, BufRead, Write};
+use std::os::fd::AsFd;
+use colored::Colorize;
+use json_comments::StripComments;
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+use rustyline::DefaultEditor;
+use crate::{config::{self, Config}, credentials, jsonc, llm, message::Message};
+
+/// Name, API address, whether it needs a key, and a suggested model.
+const PROVIDERS: &[(&str, &str, bool, &str)] = &[
+    ("OpenRouter", "https://openrouter.ai/api/v1", true, "deepseek/deepseek-chat-v3-0324:free"),
+    ("OpenAI", "https://api.openai.com/v1", true, "gpt-4o-mini"),
+    ("LM Studio (local)", "http://localhost:1234/v1", false, ""),
+    ("Ollama (local)", "http://localhost:11434/v1", false, "qwen2.5-coder"),
+    ("Other OpenAI-compatible server", "", true, ""),
+];
+
+/// Reads a line without echoing it, for the API key.
+fn read_hidden(prompt: &str) -> io::Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let stdin = io::stdin();
+    let saved = tcgetattr(stdin.as_fd()).ok();
+    if let Some(saved) = &saved {
+        let mut hidden = saved.clone();
+        hidden.local_flags.remove(LocalFlags::ECHO);
+        let _ = tcsetattr(stdin.as_fd(), SetArg::TCSANOW, &hidden);
+    }
+    let mut line = String::new();
+    let result = stdin.lock().read_line(&mut line);
+    if let Some(saved) = &saved {
+        let _ = tcsetattr(stdin.as_fd(), SetArg::TCSANOW, saved);
+        println!();
+    }
+    result.map(|_| line.trim().to_string())
+}
+
+/// The user config's current value of a text setting.
+fn current(settings: &serde_json::Value, key: &str) -> String {
+    settings[key].as_str().unwrap_or("").to_string()
+}
+
+/// `aiosc init`: asks for the provider, API key and model, tries them and saves them to the
+/// user config.
+pub fn run_init() -> Result<(), Box<dyn std::error::Error>> {
+    let path = config::get_config_path();
+    let text = std::fs::read_to_string(&path).unwrap_or_default();
+    let settings: serde_json::Value = serde_json::from_reader(StripComments::new(text.as_bytes())).unwrap_or_default();
+    let mut rl = DefaultEditor::new()?;
+
+    println!("{}", format!("Setting up {}. Press Enter to accept the suggested value.", path.display()).blue());
+    for (i, (name, ..)) in PROVIDERS.iter().enumerate() {
+        println!("  {}) {}", i + 1, name);
+    }
+    let current_addr = current(&settings, "api_addr");
+    let default = match PROVIDERS.iter().position(|(_, addr, ..)| *addr == current_addr) {
+        _ if current_addr.is_empty() => 1,
+        Some(known) => known + 1,
+        None => PROVIDERS.len(),
+    };
+    let choice = rl.readline(&format!("Provider [{}]: ", default))?;
+    let choice = if choice.trim().is_empty() { default.to_string() } else { choice.trim().to_string() };
+    let (_, addr, needs_key, model) = choice
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| PROVIDERS.get(n.wrapping_sub(1)))
+        .copied()
+        .ok_or_else(|| format!("No provider '{}'", choice))?;
+
+    let addr = if addr.is_empty() { current(&settings, "api_addr") } else { addr.to_string() };
+    let api_addr = rl.readline_with_initial("API address: ", (&addr, ""))?.trim().to_string();
+
+    let mut probe = Config::default();
+    probe.api_addr = api_addr;
+    probe.api_key = current(&settings, "api_key");
+    probe.api_key_cmd = current(&settings, "api_key_cmd");
+    probe.credential_store = match current(&settings, "credential_store") {
+        store if store.is_empty() => "file".to_string(),
+        store => store,
+    };
+    let mut entered = String::new();
+    if !probe.api_key_cmd.is_empty() {
+        println!("{}", format!("The API key comes from `{}` (api_key_cmd).", probe.api_key_cmd).truecolor(128, 128, 128));
+    } else {
+        let existing = credentials::api_key(&probe).unwrap_or_default();
+        if needs_key || !existing.is_empty() {
+            let hint = if existing.is_empty() { "" } else { " (Enter keeps the current one)" };
+            entered = read_hidden(&format!("API key{}: ", hint))?;
+        }
+        probe.api_key = if entered.is_empty() { existing } else { entered.clone() };
+    }
+
+    let mut suggested = if current(&settings, "model").is_empty() { model.to_string() } else { current(&settings, "model") };
+    match llm::list_models(&probe) {
+        Ok(models) if !models.is_empty() => {
+            if models.len() <= 20 {
+                println!("{}", format!("Models offered: {}", models.join(", ")).truecolor(128, 128, 128));
+            } else {
+                println!("{}", format!("The server offers {} models.", models.len()).truecolor(128, 128, 128));
+            }
+            if !models.contains(&suggested) {
+                suggested = if models.iter().any(|m| m == model) { model.to_string() } else { models[0].clone() };
+            }
+        }
+        Ok(_) => {}
+        Err(e) => println!("{}", format!("Could not list the models: {}", e).yellow()),
+    }
+    probe.model = rl.readline_with_initial("Model: ", (&suggested, ""))?.trim().to_string();
+
+    println!("{}", "Testing the connection...".truecolor(128, 128, 128));
+    let conversation = [Message { role: "user".to_string(), content: "Reply with the single word OK.".to_string() }];
+    match llm::query_llm(&probe, &conversation) {
+        Ok(reply) => println!("{}", format!("The model answered: {}", reply.trim()).green()),
+        Err(e) => {
+            println!("{}", format!("The test request failed: {}", e).red());
+            let answer = rl.readline("Save anyway? [y/N] ")?;
+            if !answer.trim().eq_ignore_ascii_case("y") {
+                println!("{}", "Nothing was saved.".yellow());
+                return Ok(());
+            }
+        }
+    }
+
+    let mut text = text;
+    let mut set = |key: &str, value: &str| -> Result<(), String> {
+        text = jsonc::set(&text, &[key], &serde_json::Value::String(value.to_string()))
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        Ok(())
+    };
+    set("api_addr", &probe.api_addr)?;
+    set("model", &probe.model)?;
+    if !entered.is_empty() {
+        match choose_store(&mut rl, &probe)? {
+            "config" => set("api_key", &entered)?,
+            store => {
+                if store == "keyring" || !current(&settings, "credential_store").is_empty() {
+                    set("credential_store", store)?;
+                }
+                probe.api_key.clear();
+                probe.credential_store = store.to_string();
+                let place = credentials::store(&probe, &entered)?;
+                println!("{}", format!("API key saved to {}.", place).truecolor(128, 128, 128));
+                // A key in the config would take precedence
+                if let Some(removed) = jsonc::remove(&text, &["api_key"])? {
+                    text = removed;
+                }
+            }
+        }
+    }
+    config::write_config_file(&path, &text)?;
+    println!("{}", format!("Saved to {}. Run `aiosc` to start.", path.display()).green());
+    Ok(())
+}
+
+/// Asks where to keep a new API key: `file`, `keyring` or `config`.
+fn choose_store(rl: &mut DefaultEditor, config: &Config) -> Result<&'static str, Box<dyn std::error::Error>> {
+    let mut stores = vec![("file", format!("the credentials file ({})", credentials::credentials_path().display()))];
+    if credentials::keyring_available() {
+        stores.push(("keyring", "the system keyring (Secret Service)".to_string()));
+    }
+    stores.push(("config", "the config file".to_string()));
+    println!("Keep the API key in:");
+    for (i, (_, description)) in stores.iter().enumerate() {
+        println!("  {}) {}", i + 1, description);
+    }
+    let default = stores.iter().position(|(store, _)| credentials::uses_keyring(config) && *store == "keyring").unwrap_or(0) + 1;
+    let choice = rl.readline(&format!("Store [{}]: ", default))?;
+    let choice = if choice.trim().is_empty() { default.to_string() } else { choice.trim().to_string() };
+    choice
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| stores.get(n.wrapping_sub(1)))
+        .map(|(store, _)| *store)
+        .ok_or_else(|| format!("No choice '{}'", choice).into())
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+/// Set when the user asked to stop the current task: Ctrl-C outside a command, a second Ctrl-C
+/// or Ctrl-] while one is running.
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_: nix::libc::c_int) {
+    ABORTED.store(true, Ordering::Relaxed);
+}
+
+/// While alive, Ctrl-C aborts the current task instead of quitting aiosc.
+pub struct Guard {
+    previous: Option<SigAction>,
+}
+
+/// Starts a task: clears an earlier abort and catches Ctrl-C until the guard is dropped.
+pub fn guard() -> Guard {
+    ABORTED.store(false, Ordering::Relaxed);
+    let handler = SigAction::new(SigHandler::Handler(on_sigint), SaFlags::empty(), SigSet::empty());
+    Guard { previous: unsafe { sigaction(Signal::SIGINT, &handler) }.ok() }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(previous) = &self.previous {
+            unsafe { let _ = sigaction(Signal::SIGINT, previous); }
+        }
+    }
+}
+
+pub fn abort() {
+    ABORTED.store(true, Ordering::Relaxed);
+}
+
+pub fn aborted() -> bool {
+    ABORTED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,274 @@
+use serde_json::Value;
+
+/// A value in a JSON-with-comments text, with its byte range. Only objects are broken down
+/// further, since edits address object members.
+enum Node {
+    Object { start: usize, end: usize, members: Vec<Member> },
+    Other { end: usize },
+}
+
+struct Member {
+    key: String,
+    key_start: usize,
+    value_start: usize,
+    value: Node,
+}
+
+impl Node {
+    fn end(&self) -> usize {
+        match self {
+            Node::Object { end, .. } | Node::Other { end } => *end,
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    /// Skips whitespace and comments.
+    fn skip(&mut self) {
+        loop {
+            let rest = &self.text[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.pos += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn error(&self, what: &str) -> String {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        format!("{} at line {}", what, line)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                b'\\' => self.pos += 1,
+                b'"' => return serde_json::from_str(&self.text[start..self.pos]).map_err(|e| self.error(&e.to_string())),
+                _ => {}
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn value(&mut self) -> Result<Node, String> {
+        self.skip();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => {
+                self.pos += 1;
+                self.skip();
+                if self.peek() != Some(b']') {
+                    loop {
+                        self.value()?;
+                        self.skip();
+                        match self.peek() {
+                            Some(b',') => self.pos += 1,
+                            Some(b']') => break,
+                            _ => return Err(self.error("expected ',' or ']'")),
+                        }
+                    }
+                }
+                self.pos += 1;
+                Ok(Node::Other { end: self.pos })
+            }
+            Some(b'"') => {
+                self.string()?;
+                Ok(Node::Other { end: self.pos })
+            }
+            Some(_) => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || b"+-.".contains(&c)) {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return Err(self.error("expected a value"));
+                }
+                Ok(Node::Other { end: self.pos })
+            }
+            None => Err(self.error("unexpected end of file")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Node, String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.skip();
+        if self.peek() != Some(b'}') {
+            loop {
+                self.skip();
+                if self.peek() != Some(b'"') {
+                    return Err(self.error("expected a key"));
+                }
+                let key_start = self.pos;
+                let key = self.string()?;
+                self.skip();
+                if self.peek() != Some(b':') {
+                    return Err(self.error("expected ':'"));
+                }
+                self.pos += 1;
+                self.skip();
+                let value_start = self.pos;
+                let value = self.value()?;
+                members.push(Member { key, key_start, value_start, value });
+                self.skip();
+                match self.peek() {
+                    Some(b',') => self.pos += 1,
+                    Some(b'}') => break,
+                    _ => return Err(self.error("expected ',' or '}'")),
+                }
+            }
+        }
+        self.pos += 1;
+        Ok(Node::Object { start, end: self.pos, members })
+    }
+}
+
+fn parse(text: &str) -> Result<Node, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let root = parser.value()?;
+    match root {
+        Node::Object { .. } => Ok(root),
+        Node::Other { .. } => Err("the config must be a JSON object".to_string()),
+    }
+}
+
+/// The whitespace a line starts with.
+fn indentation_at(text: &str, pos: usize) -> &str {
+    let line_start = text[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Pretty-prints a value for a place indented by `indent`.
+fn render(value: &Value, indent: &str) -> String {
+    let pretty = serde_json::to_string_pretty(value).unwrap_or_default();
+    pretty.replace('\n', &format!("\n{}", indent))
+}
+
+/// `{"a": {"b": value}}` for the path `["a", "b"]`.
+fn nested(path: &[&str], value: &Value) -> Value {
+    path.iter().rev().fold(value.clone(), |inner, key| serde_json::json!({ *key: inner }))
+}
+
+/// Sets the value at `path`, creating the objects on the way, and keeps the rest of the text,
+/// comments included, as it is.
+pub fn set(text: &str, path: &[&str], value: &Value) -> Result<String, String> {
+    let text = if text.trim().is_empty() { "{}\n" } else { text };
+    let mut node = parse(text)?;
+    for (depth, key) in path.iter().enumerate() {
+        let Node::Object { start, end, members } = node else { unreachable!("only objects are descended into") };
+        let Some(member) = members.into_iter().find(|m| m.key == *key) else {
+            return Ok(insert(text, start, end, key, &nested(&path[depth + 1..], value)));
+        };
+        if depth + 1 == path.len() || !matches!(member.value, Node::Object { .. }) {
+            let indent = indentation_at(text, member.key_start);
+            let replacement = render(&nested(&path[depth + 1..], value), indent);
+            return Ok(format!("{}{}{}", &text[..member.value_start], replacement, &text[member.value.end()..]));
+        }
+        node = member.value;
+    }
+    Err("empty setting name".to_string())
+}
+
+/// Adds `"key": value` as the last member of the object spanning `start..end`.
+fn insert(text: &str, start: usize, end: usize, key: &str, value: &Value) -> String {
+    let close = end - 1;
+    let key = serde_json::to_string(key).unwrap_or_default();
+    let Ok(Node::Object { members, .. }) = (Parser { text, pos: start }).object() else { return text.to_string() };
+
+    let Some(last) = members.last() else {
+        // Keep comments inside the empty object
+        let outer = indentation_at(text, start);
+        let inner = format!("{}  ", outer);
+        let body = text[start + 1..close].trim_end();
+        return format!("{}{{{}\n{}{}: {}\n{}}}{}", &text[..start], body, inner, key, render(value, &inner), outer, &text[end..]);
+    };
+    let last_end = last.value.end();
+    if !text[start..end].contains('\n') {
+        return format!("{}, {}: {}{}", &text[..last_end], key, serde_json::to_string(value).unwrap_or_default(), &text[last_end..]);
+    }
+    // A comment on the last member's line stays with it
+    let indent = indentation_at(text, last.key_start).to_string();
+    let rest = &text[last_end..close];
+    let line_end = match rest.find('\n') {
+        Some(offset) if rest[..offset].trim().is_empty() || rest[..offset].trim_start().starts_with("//") => last_end + offset,
+        _ => last_end,
+    };
+    format!(
+        "{},{}\n{}{}: {}{}",
+        &text[..last_end],
+        &text[last_end..line_end],
+        indent,
+        key,
+        render(value, &indent),
+        &text[line_end..]
+    )
+}
+
+/// Removes the member at `path`, with a comment on its line. Returns `None` if it isn't there.
+pub fn remove(text: &str, path: &[&str]) -> Result<Option<String>, String> {
+    let mut node = parse(text)?;
+    for (depth, key) in path.iter().enumerate() {
+        let Node::Object { start, members, .. } = node else { return Ok(None) };
+        let Some(index) = members.iter().position(|m| m.key == *key) else { return Ok(None) };
+        if depth + 1 < path.len() {
+            node = members.into_iter().nth(index).expect("member").value;
+            continue;
+        }
+
+        let member = &members[index];
+        let line_start = text[..member.key_start].rfind('\n').map_or(0, |i| i + 1);
+        let own_line = line_start > start && text[line_start..member.key_start].trim().is_empty();
+        let value_end = member.value.end();
+        let after = &text[value_end..];
+        let comma = after.find(|c: char| !c.is_whitespace()).filter(|&i| after[i..].starts_with(',') && members.len() > index + 1);
+
+        let (from, to) = match comma {
+            Some(comma) => {
+                // Through the comma, and the rest of the line if nothing else is on it
+                let rest = &after[comma + 1..];
+                let line = &rest[..rest.find('\n').map_or(rest.len(), |i| i + 1)];
+                let to = if own_line && (line.trim().is_empty() || line.trim_start().starts_with("//")) {
+                    value_end + comma + 1 + line.len()
+                } else {
+                    value_end + comma + 1 + (rest.len() - rest.trim_start_matches([' ', '\t']).len())
+                };
+                (if own_line { line_start } else { member.key_start }, to)
+            }
+            None => {
+                // The last member: from the comma after the previous one, or the opening brace
+                let from = match index.checked_sub(1).map(|i| &members[i]) {
+                    Some(previous) => {
+                        let previous_end = previous.value.end();
+                        previous_end + text[previous_end..member.key_start].find(',').unwrap_or(0)
+                    }
+                    None if own_line => line_start - 1,
+                    None => member.key_start,
+                };
+                let line = &after[..after.find('\n').unwrap_or(after.len())];
+                let to = if line.trim_start().starts_with("//") { value_end + line.len() } else { value_end };
+                (from, to)
+            }
+        };
+        return Ok(Some(format!("{}{}", &text[..from], &text[to..])));
+    }
+    Ok(None)
+}
@@ -0,0 +1,23 @@
+//! aiosc as a library: an [`agent::Agent`] works through a task with an [`llm::LlmClient`] and a
+//! [`executor::CommandRunner`], reporting to [`agent::Hooks`]. The `aiosc` binary is a terminal
+//! front end for it.
+
+pub mod agent;
+pub mod audit;
+pub mod cassette;
+pub mod cli;
+pub mod config;
+pub mod credentials;
+pub mod executor;
+pub mod init;
+pub mod interrupt;
+pub mod jsonc;
+pub mod limits;
+pub mod llm;
+pub mod message;
+pub mod redact;
+pub mod safety;
+pub mod sandbox;
+pub mod shell;
+pub mod snapshot;
+pub mod usage;
@@ -0,0 +1,305 @@
+use std::ffi::CString;
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use crate::config::Limits;
+
+const MB: u64 = 1024 * 1024;
+
+static CGROUP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// The group the per-command groups go into when `cgroup_parent` isn't set, set up once.
+static SESSION_CGROUP: OnceLock<Result<PathBuf, String>> = OnceLock::new();
+
+pub fn has_rlimits(limits: &Limits) -> bool {
+    limits.cpu_seconds > 0 || limits.address_space_mb > 0 || limits.open_files > 0 || limits.processes > 0 || limits.file_size_mb > 0
+}
+
+pub fn has_cgroup_limits(limits: &Limits) -> bool {
+    !limits.cgroup_memory_max.is_empty() || !limits.cgroup_cpu_max.is_empty() || limits.cgroup_pids_max > 0
+}
+
+/// Sets the configured rlimits in the child between fork and exec.
+pub fn apply_rlimits(cmd: &mut Command, limits: &Limits) {
+    if !has_rlimits(limits) {
+        return;
+    }
+    // (resource, soft, hard). The CPU hard limit leaves a grace period after SIGXCPU.
+    let settings = [
+        (nix::libc::RLIMIT_CPU, limits.cpu_seconds, limits.cpu_seconds + 5),
+        (nix::libc::RLIMIT_AS, limits.address_space_mb * MB, limits.address_space_mb * MB),
+        (nix::libc::RLIMIT_NOFILE, limits.open_files, limits.open_files),
+        (nix::libc::RLIMIT_NPROC, limits.processes, limits.processes),
+        (nix::libc::RLIMIT_FSIZE, limits.file_size_mb * MB, limits.file_size_mb * MB),
+    ];
+    unsafe {
+        cmd.pre_exec(move || {
+            for (resource, soft, hard) in settings {
+                if soft == 0 {
+                    continue;
+                }
+                let limit = nix::libc::rlimit { rlim_cur: soft as _, rlim_max: hard as _ };
+                if nix::libc::setrlimit(resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// A per-command cgroup v2 group. Removed again when dropped.
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+#[derive(Default)]
+pub struct CgroupEvents {
+    pub oom_kills: u64,
+    pub pids_max_hits: u64,
+    /// CPU time the command used, in seconds.
+    pub cpu_seconds: f64,
+}
+
+/// CPU time (user and system) of the terminated children aiosc has waited for, in seconds.
+pub fn children_cpu_seconds() -> f64 {
+    let mut usage: nix::libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { nix::libc::getrusage(nix::libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+        return 0.0;
+    }
+    let seconds = |t: nix::libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1_000_000.0;
+    seconds(usage.ru_utime) + seconds(usage.ru_stime)
+}
+
+/// Where the cgroup v2 hierarchy is mounted. Hybrid setups mount it below /sys/fs/cgroup/unified.
+fn cgroup2_mount() -> Option<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    mountinfo.lines().find_map(|line| {
+        let (mount, fs_type) = line.split_once(" - ")?;
+        if !fs_type.starts_with("cgroup2 ") {
+            return None;
+        }
+        mount.split_whitespace().nth(4).map(PathBuf::from)
+    })
+}
+
+fn current_cgroup() -> Result<PathBuf, String> {
+    let mount = cgroup2_mount().ok_or("cgroup v2 is not mounted")?;
+    let content = fs::read_to_string("/proc/self/cgroup").map_err(|e| format!("cannot read /proc/self/cgroup: {}", e))?;
+    let relative = content
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or("aiosc is not in a cgroup v2 group")?;
+    let relative = relative.trim_start_matches('/');
+    Ok(if relative.is_empty() { mount } else { mount.join(relative) })
+}
+
+/// Moves aiosc itself into `<its cgroup>/aiosc-<pid>/self` and returns `<its cgroup>/aiosc-<pid>`,
+/// where the command groups go. A group can only hand controllers to its children while it has
+/// no processes of its own, so aiosc can't create limited groups right next to itself. aiosc
+/// stays in the leaf for the rest of the session.
+fn session_cgroup() -> Result<PathBuf, String> {
+    let current = current_cgroup()?;
+    let procs = fs::read_to_string(current.join("cgroup.procs")).map_err(|e| format!("cannot read '{}': {}", current.display(), e))?;
+    let pid = std::process::id().to_string();
+    if procs.lines().any(|p| p.trim() != pid) {
+        return Err(format!(
+            "aiosc shares its cgroup '{}' with other processes; run it in its own delegated scope (`systemd-run --user --scope -p Delegate=yes aiosc`) or set limits.cgroup_parent to a delegated group",
+            current.display()
+        ));
+    }
+    let available = fs::read_to_string(current.join("cgroup.controllers"))
+        .map_err(|_| format!("'{}' is not a cgroup v2 group", current.display()))?;
+    let controllers: Vec<&str> = ["cpu", "memory", "pids"].into_iter().filter(|c| available.split_whitespace().any(|a| a == *c)).collect();
+
+    // Groups of earlier sessions whose aiosc is gone. Other sessions' groups stay, and the
+    // kernel refuses to remove populated ones anyway.
+    if let Ok(entries) = fs::read_dir(&current) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(owner) = name.strip_prefix("aiosc-").and_then(|p| p.parse::<i32>().ok()) else { continue };
+            if process_exists(owner) {
+                continue;
+            }
+            if let Ok(children) = fs::read_dir(entry.path()) {
+                for child in children.flatten().filter(|c| c.path().is_dir()) {
+                    let _ = fs::remove_dir(child.path());
+                }
+            }
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+
+    let session = current.join(format!("aiosc-{}", pid));
+    let leaf = session.join("self");
+    for group in [&session, &leaf] {
+        if let Err(e) = fs::create_dir(group) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(format!("cannot create cgroup '{}': {}", group.display(), e));
+            }
+        }
+    }
+    write_control(&leaf.join("cgroup.procs"), &pid).map_err(|e| format!("cannot move aiosc into '{}': {}", leaf.display(), e))?;
+    enable_controllers(&current, &controllers)?;
+    Ok(session)
+}
+
+fn process_exists(pid: i32) -> bool {
+    !matches!(nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None), Err(nix::errno::Errno::ESRCH))
+}
+
+fn check_available(group: &Path, controllers: &[&str]) -> Result<(), String> {
+    let available = fs::read_to_string(group.join("cgroup.controllers"))
+        .map_err(|_| format!("'{}' is not a cgroup v2 group", group.display()))?;
+    match controllers.iter().find(|c| !available.split_whitespace().any(|a| a == **c)) {
+        Some(controller) => Err(format!("the {} controller is not available in '{}'", controller, group.display())),
+        None => Ok(()),
+    }
+}
+
+/// Lets the children of `group` use `controllers`.
+fn enable_controllers(group: &Path, controllers: &[&str]) -> Result<(), String> {
+    let enabled = fs::read_to_string(group.join("cgroup.subtree_control")).unwrap_or_default();
+    let missing: Vec<String> = controllers
+        .iter()
+        .filter(|c| !enabled.split_whitespace().any(|e| e == **c))
+        .map(|c| format!("+{}", c))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    write_control(&group.join("cgroup.subtree_control"), &missing.join(" "))
+        .map_err(|e| format!("cannot enable {} in '{}': {}", missing.join(" "), group.display(), e))
+}
+
+/// Writes an existing cgroup interface file. Never creates files, so a wrong path fails instead
+/// of silently writing to a regular directory.
+fn write_control(path: &Path, value: &str) -> std::io::Result<()> {
+    fs::OpenOptions::new().write(true).open(path)?.write_all(value.as_bytes())
+}
+
+fn read_event(content: &str, key: &str) -> u64 {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+impl Cgroup {
+    /// Creates a child group with the configured limits, or `None` when no cgroup limit is set.
+    pub fn create(limits: &Limits) -> Result<Option<Cgroup>, String> {
+        if !has_cgroup_limits(limits) {
+            return Ok(None);
+        }
+        let mut controllers = Vec::new();
+        if !limits.cgroup_memory_max.is_empty() { controllers.push("memory"); }
+        if !limits.cgroup_cpu_max.is_empty() { controllers.push("cpu"); }
+        if limits.cgroup_pids_max > 0 { controllers.push("pids"); }
+
+        let parent = if limits.cgroup_parent.is_empty() {
+            SESSION_CGROUP.get_or_init(session_cgroup).clone()?
+        } else {
+            PathBuf::from(&limits.cgroup_parent)
+        };
+        check_available(&parent, &controllers)?;
+        enable_controllers(&parent, &controllers)?;
+
+        let name = format!("aiosc-{}-{}", std::process::id(), CGROUP_COUNTER.fetch_add(1, Ordering::Relaxed));
+        let path = parent.join(name);
+        fs::create_dir(&path).map_err(|e| format!("cannot create cgroup '{}': {}", path.display(), e))?;
+        let cgroup = Cgroup { path };
+
+        let mut settings = vec![];
+        if !limits.cgroup_memory_max.is_empty() {
+            settings.push(("memory.max", limits.cgroup_memory_max.clone()));
+        }
+        if !limits.cgroup_cpu_max.is_empty() {
+            settings.push(("cpu.max", limits.cgroup_cpu_max.clone()));
+        }
+        if limits.cgroup_pids_max > 0 {
+            settings.push(("pids.max", limits.cgroup_pids_max.to_string()));
+        }
+        for (file, value) in settings {
+            write_control(&cgroup.path.join(file), &value).map_err(|e| format!("cannot set {} to '{}': {}", file, value, e))?;
+        }
+        if !limits.cgroup_memory_max.is_empty() {
+            // Without this the memory limit only pushes the command into swap
+            let _ = write_control(&cgroup.path.join("memory.swap.max"), "0");
+        }
+        Ok(Some(cgroup))
+    }
+
+    /// Moves the child into this group between fork and exec.
+    pub fn attach(&self, cmd: &mut Command) {
+        let procs = CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes()).expect("cgroup path");
+        unsafe {
+            cmd.pre_exec(move || {
+                let fd = nix::libc::open(procs.as_ptr(), nix::libc::O_WRONLY);
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let written = nix::libc::write(fd, b"0".as_ptr() as *const _, 1);
+                nix::libc::close(fd);
+                if written != 1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    pub fn events(&self) -> CgroupEvents {
+        let memory = fs::read_to_string(self.path.join("memory.events")).unwrap_or_default();
+        let pids = fs::read_to_string(self.path.join("pids.events")).unwrap_or_default();
+        let cpu = fs::read_to_string(self.path.join("cpu.stat")).unwrap_or_default();
+        CgroupEvents {
+            oom_kills: read_event(&memory, "oom_kill "),
+            pids_max_hits: read_event(&pids, "max "),
+            cpu_seconds: read_event(&cpu, "usage_usec ") as f64 / 1_000_000.0,
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Kill anything the command left behind, otherwise the group can't be removed
+        let _ = write_control(&self.path.join("cgroup.kill"), "1");
+        for _ in 0..10 {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+    }
+}
+
+/// Describes which configured limit a finished command most likely ran into. A SIGKILL is only
+/// put down to `cpu_seconds` when the command used that much CPU time and no OOM kill happened.
+pub fn describe_limit_hit(limits: &Limits, signal: Option<&str>, output: &str, events: &CgroupEvents) -> Option<String> {
+    let cpu_exhausted = limits.cpu_seconds > 0 && events.cpu_seconds >= limits.cpu_seconds as f64;
+    let (limit, value, detail) = if events.oom_kills > 0 {
+        ("cgroup_memory_max", limits.cgroup_memory_max.clone(), "process killed by the OOM killer".to_string())
+    } else if signal == Some("SIGXCPU") || (signal == Some("SIGKILL") && cpu_exhausted) {
+        ("cpu_seconds", limits.cpu_seconds.to_string(), format!("process killed by {}", signal.unwrap_or("signal")))
+    } else if signal == Some("SIGXFSZ") || (limits.file_size_mb > 0 && output.contains("File too large")) {
+        ("file_size_mb", limits.file_size_mb.to_string(), "a file exceeded the maximum size".to_string())
+    } else if events.pids_max_hits > 0 {
+        ("cgroup_pids_max", limits.cgroup_pids_max.to_string(), "process creation was refused".to_string())
+    } else if limits.address_space_mb > 0 && ["Cannot allocate memory", "MemoryError", "bad_alloc", "out of memory"].iter().any(|p| output.contains(p)) {
+        ("address_space_mb", limits.address_space_mb.to_string(), "memory allocation failed".to_string())
+    } else if limits.processes > 0 && (output.contains("Resource temporarily unavailable") || output.contains("fork: retry")) {
+        ("processes", limits.processes.to_string(), "process creation was refused".to_string())
+    } else if limits.open_files > 0 && output.contains("Too many open files") {
+        ("open_files", limits.open_files.to_string(), "no more files could be opened".to_string())
+    } else {
+        return None;
+    };
+    Some(format!(
+        "Error: Resource limit exceeded [limit={} value={}]: {}. Use a less resource-intensive approach instead of retrying.",
+        limit, value, detail
+    ))
+}
@@ -1,42 +1,151 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
 use reqwest::blocking::Client;
-use serde_json;
 use colored::Colorize;
-use crate::{config::Config, message::Message};
+use crate::{cassette::{self, Reply}, config::Config, credentials, executor::wildcard_match, interrupt, message::Message, redact, usage};
 
-pub fn query_llm(config: &Config, conversation: &[Message]) -> Result<String, Box<dyn std::error::Error>> {
-    let client = Client::new();
-    let url = format!("{}/chat/completions", config.api_addr);
+/// Something that answers a conversation, usually a model behind an API.
+pub trait LlmClient {
+    fn query(&mut self, config: &Config, conversation: &[Message]) -> Result<String, Box<dyn std::error::Error>>;
+}
+
+/// The OpenAI-compatible chat completions API at `api_addr`.
+pub struct HttpClient;
 
+impl LlmClient for HttpClient {
+    fn query(&mut self, config: &Config, conversation: &[Message]) -> Result<String, Box<dyn std::error::Error>> {
+        query_llm(config, conversation)
+    }
+}
+
+/// Sends the conversation to the model. The request runs on a worker thread so that aborting the
+/// task (Ctrl-C) returns right away; the abandoned request finishes in the background, where its
+/// answer is dropped without being recorded, though its usage is counted.
+pub fn query_llm(config: &Config, conversation: &[Message]) -> Result<String, Box<dyn std::error::Error>> {
     if config.debug {
-        let pretty_in = serde_json::to_string_pretty(conversation)?;
+        let (pretty_in, _) = redact::redact(config, &serde_json::to_string_pretty(conversation)?);
         println!("{}", format!("[API request]\n{}", pretty_in).truecolor(128, 128, 128));
+        let params = model_params(config);
+        if !params.is_empty() {
+            println!("{}", format!("[API params] {}", serde_json::Value::Object(params)).truecolor(128, 128, 128));
+        }
     }
 
-    let mut request = client
-        .post(&url)
-        .header("Content-Type", "application/json")
-        .json(&serde_json::json!({ "model": &config.model, "messages": conversation }));
-
-    if !config.api_key.is_empty() {
-        request = request.header("Authorization", format!("Bearer {}", config.api_key));
+    let (sender, receiver) = mpsc::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (config, conversation, worker_cancelled) = (config.clone(), conversation.to_vec(), cancelled.clone());
+    std::thread::spawn(move || {
+        let _ = sender.send(send_request(&config, &conversation, &worker_cancelled));
+    });
+    loop {
+        if interrupt::aborted() {
+            cancelled.store(true, Ordering::SeqCst);
+            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Interrupted, "Request cancelled")));
+        }
+        match receiver.recv_timeout(Duration::from_millis(50)) {
+            Ok(result) => return result.map_err(|e| e as Box<dyn std::error::Error>),
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return Err(Box::new(std::io::Error::other("The LLM request failed unexpectedly"))),
+        }
     }
+}
+
+/// Fields aiosc sets itself, which `model_params` can't override.
+const RESERVED_PARAMS: &[&str] = &["model", "messages", "stream"];
 
-    let res = match request.send() {
-        Ok(res) => {
-            if res.status().is_success() { res } else {
-                return Err(match res.status().as_u16() {
-                    401 => Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Invalid API key or authentication failed")),
-                    404 => Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "API endpoint not found")),
-                    code => Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("API request failed with status code: {}", code))),
-                });
+/// The `model_params` that apply to the configured model. Every pattern matching the model name
+/// applies, longer patterns overriding shorter ones, so `"*"` holds defaults for all models and
+/// the exact name has the last word.
+pub fn model_params(config: &Config) -> serde_json::Map<String, serde_json::Value> {
+    let mut patterns: Vec<&String> = config.model_params.keys().filter(|p| wildcard_match(p, &config.model)).collect();
+    patterns.sort_by_key(|p| (**p == config.model, p.len()));
+    let mut params = serde_json::Map::new();
+    for pattern in patterns {
+        for (key, value) in &config.model_params[pattern] {
+            if !RESERVED_PARAMS.contains(&key.as_str()) {
+                params.insert(key.clone(), value.clone());
             }
         }
-        Err(e) => return Err(if e.is_connect() {
-            Box::new(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "Failed to connect to the LLM server"))
-        } else { Box::new(e) }),
+    }
+    params
+}
+
+/// The chat completions request body: the model, the conversation and the model's parameters.
+pub fn request_body(config: &Config, conversation: &[Message]) -> serde_json::Value {
+    let mut body = model_params(config);
+    body.insert("model".to_string(), serde_json::json!(config.model));
+    body.insert("messages".to_string(), serde_json::json!(conversation));
+    serde_json::Value::Object(body)
+}
+
+/// `cancelled` is set once nobody waits for the answer anymore. The answer is then dropped and
+/// not recorded, but its usage still counts, since the request was made.
+fn send_request(config: &Config, conversation: &[Message], cancelled: &AtomicBool) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let request = request_body(config, conversation);
+    let reply = if !config.replay.is_empty() {
+        let (reply, matched) = cassette::replay(&config.replay, &request)?;
+        if config.debug && !matched {
+            println!("{}", "[Replay] No recorded request matches; serving the next response in order".truecolor(128, 128, 128));
+        }
+        reply
+    } else {
+        let api_key = credentials::api_key(config)?;
+        let reply = post(config, &api_key, &request);
+        if !config.record.is_empty() && !cancelled.load(Ordering::SeqCst) {
+            cassette::record(&config.record, &request, &reply)?;
+        }
+        reply
     };
 
-    let json: serde_json::Value = res.json().map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
-    let content = json["choices"][0]["message"]["content"].as_str().unwrap_or("").to_string();
-    Ok(content)
-}
\ No newline at end of file
+    match reply.status {
+        0 => Err(Box::new(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, reply.body))),
+        200..=299 => {
+            let json: serde_json::Value = serde_json::from_str(&reply.body)?;
+            usage::record(config, &json);
+            if cancelled.load(Ordering::SeqCst) {
+                return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Interrupted, "Request cancelled")));
+            }
+            Ok(json["choices"][0]["message"]["content"].as_str().unwrap_or("").to_string())
+        }
+        401 => Err(Box::new(std::io::Error::other("Invalid API key or authentication failed"))),
+        404 => Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "API endpoint not found"))),
+        code => Err(Box::new(std::io::Error::other(format!("API request failed with status code: {}", code)))),
+    }
+}
+
+/// Sends the request to the API. Failures to get any answer become a `Reply` with status 0, so
+/// they can be recorded like any other outcome.
+fn post(config: &Config, api_key: &str, request: &serde_json::Value) -> Reply {
+    let client = Client::new();
+    let url = format!("{}/chat/completions", config.api_addr);
+
+    let mut builder = client.post(&url).header("Content-Type", "application/json").json(request);
+    if !api_key.is_empty() {
+        builder = builder.header("Authorization", format!("Bearer {}", api_key));
+    }
+
+    match builder.send().and_then(|res| Ok((res.status().as_u16(), res.text()?))) {
+        Ok((status, body)) => Reply { status, body },
+        Err(e) if e.is_connect() => Reply { status: 0, body: "Failed to connect to the LLM server".to_string() },
+        Err(e) => Reply { status: 0, body: e.to_string() },
+    }
+}
+
+/// The model names the server offers (`GET /models`).
+pub fn list_models(config: &Config) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let mut request = client.get(format!("{}/models", config.api_addr));
+    let api_key = credentials::api_key(config)?;
+    if !api_key.is_empty() {
+        request = request.header("Authorization", format!("Bearer {}", api_key));
+    }
+    let json: serde_json::Value = request.send()?.error_for_status()?.json()?;
+    let mut models: Vec<String> = json["data"]
+        .as_array()
+        .map(|models| models.iter().filter_map(|m| m["id"].as_str().map(str::to_string)).collect())
+        .unwrap_or_default();
+    models.sort();
+    Ok(models)
+}
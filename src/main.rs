@@ -1,58 +1,92 @@
-use cli::{run_cli, run_non_interactive};
-use config::load_config;
+use aiosc::cli::{run_cli, run_non_interactive};
+use aiosc::config::{self, load_config};
+use aiosc::{audit, cassette, init, sandbox, shell, snapshot};
 use colored::Colorize;
-mod config;
-mod message;
-mod cli;
-mod llm;
-mod executor;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut config = load_config();
     let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 && args[1] == "config" {
+        return config::run_config_command(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "init" {
+        return init::run_init();
+    }
+    let mut config = load_config();
 
-    if args.len() > 1 {
+    if args.len() > 1 && args[1] == "audit" {
+        audit::run_audit_command(&config, &args[2..])?;
+    } else {
         let mut no_confirm = false;
         let mut silent = false;
         let mut prompt_parts = Vec::new();
         let mut i = 1;
 
+        // The profile goes first, so that other flags override its settings
+        if let Some(pos) = args.iter().position(|arg| arg == "--profile") {
+            let Some(name) = args.get(pos + 1) else {
+                return Err("--profile needs a profile name".into());
+            };
+            config = config::apply_profile(&config, name)?;
+        }
+
         // Parse flags and collect prompt
         while i < args.len() {
             match args[i].as_str() {
                 "--no-confirm" => no_confirm = true,
                 "--silent" => silent = true,
+                "--dry-run" => config.dry_run = true,
+                "--read-only" => config.read_only = true,
+                "--sandbox" => if !sandbox::is_enabled(&config) { config.sandbox = "auto".to_string() },
+                flag if flag.starts_with("--sandbox=") => config.sandbox = flag["--sandbox=".len()..].to_string(),
+                "--profile" => i += 1,
+                flag @ ("--record" | "--replay") => {
+                    let Some(path) = args.get(i + 1) else {
+                        return Err(format!("{} needs a cassette file", flag).into());
+                    };
+                    if flag == "--record" { config.record = path.clone() } else { config.replay = path.clone() }
+                    i += 1;
+                }
                 _ => prompt_parts.push(args[i].clone()),
             }
             i += 1;
         }
 
-        if prompt_parts.is_empty() {
-            println!("{}", "Error: No prompt provided after flags.".red());
-            return Ok(());
+        if !config.record.is_empty() && !config.replay.is_empty() {
+            return Err("--record and --replay can't be used together".into());
+        }
+        if !config.record.is_empty() {
+            cassette::start_recording(&config.record).map_err(|e| format!("Failed to create cassette '{}': {}", config.record, e))?;
         }
-
-        let prompt = prompt_parts.join(" ");
         if no_confirm {
             config.require_confirmation = false;
         }
-        run_non_interactive(config, &prompt, silent)?;
-    } else {
-    println!(
-        "{}",
-        format!(
-            "{}\n{}\n{}\n{}\nv{}",
-            "   _   ___ __   ___  ___",
-            "  /_\\ |_ _/ _ \\/ __|/ __|",
-            " / _ \\ | | (_) \\__ \\ (__ ",
-            "/_/ \\_\\___\\___/|___/\\___|",
-            env!("CARGO_PKG_VERSION")
-        )
-        .blue()
-    );
-    run_cli(config)?;
-    println!("{}", "Goodbye!".blue());
+        if !prompt_parts.is_empty() {
+            let prompt = prompt_parts.join(" ");
+            run_non_interactive(config, &prompt, silent)?;
+        } else {
+            // Without a prompt, flags such as `--read-only` apply to an interactive session
+            println!(
+                "{}",
+                format!(
+                    "{}\n{}\n{}\n{}\nv{}",
+                    "   _   ___ __   ___  ___",
+                    "  /_\\ |_ _/ _ \\/ __|/ __|",
+                    " / _ \\ | | (_) \\__ \\ (__ ",
+                    "/_/ \\_\\___\\___/|___/\\___|",
+                    env!("CARGO_PKG_VERSION")
+                )
+                .blue()
+            );
+            if config.read_only {
+                println!("{}", "Read-only mode: the AI can only run inspection commands.".yellow());
+            }
+            run_cli(config)?;
+            println!("{}", "Goodbye!".blue());
+        }
     }
 
+    shell::close();
+    sandbox::cleanup();
+    snapshot::cleanup();
     Ok(())
 }
\ No newline at end of file
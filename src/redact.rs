@@ -0,0 +1,119 @@
+use std::sync::{Mutex, OnceLock};
+use colored::Colorize;
+use regex::{Captures, Regex};
+use crate::config::Config;
+
+struct Pattern {
+    kind: String,
+    regex: Regex,
+    /// Capture group holding the secret; 0 masks the whole match.
+    group: usize,
+}
+
+#[derive(Clone)]
+pub struct Redaction {
+    pub placeholder: String,
+    pub secret: String,
+}
+
+// (kind, pattern, group). Applied in order, so specific formats go before generic assignments.
+const BUILTIN_PATTERNS: &[(&str, &str, usize)] = &[
+    ("private_key", r"-----BEGIN [A-Z0-9 ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z0-9 ]*PRIVATE KEY-----", 0),
+    ("url_password", r"\b[a-zA-Z][a-zA-Z0-9+.-]*://[^/\s:@]+:([^/\s@\[]+)@", 1),
+    ("jwt", r"\beyJ[A-Za-z0-9_-]{8,}\.[A-Za-z0-9_-]{8,}\.[A-Za-z0-9_-]{8,}", 0),
+    ("aws_access_key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b", 0),
+    ("aws_secret_key", r#"(?i)aws_secret_access_key["']?\s*[=:]\s*["']?([A-Za-z0-9/+=]{40})"#, 1),
+    ("github_token", r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})\b", 0),
+    ("slack_token", r"\bxox[abprs]-[A-Za-z0-9-]{10,}", 0),
+    ("api_key", r"\bsk-[A-Za-z0-9_-]{20,}", 0),
+    ("bearer_token", r"(?i)\bbearer\s+([A-Za-z0-9._~+/=-]{8,})", 1),
+    (
+        "secret_assignment",
+        r#"(?i)\b[A-Za-z0-9_.-]*(?:password|passwd|secret|token|api[_-]?key|access[_-]?key|private[_-]?key)[A-Za-z0-9_.-]*["']?\s*[=:]\s*["']?([^\s"',;}$\[][^\s"',;}]{2,})"#,
+        1,
+    ),
+];
+
+static BUILTIN: OnceLock<Vec<Pattern>> = OnceLock::new();
+static REDACTIONS: Mutex<Vec<Redaction>> = Mutex::new(Vec::new());
+
+fn builtin_patterns() -> &'static [Pattern] {
+    BUILTIN.get_or_init(|| {
+        BUILTIN_PATTERNS
+            .iter()
+            .map(|(kind, pattern, group)| Pattern {
+                kind: kind.to_string(),
+                regex: Regex::new(pattern).expect("built-in redaction pattern"),
+                group: *group,
+            })
+            .collect()
+    })
+}
+
+fn custom_patterns(config: &Config) -> Vec<Pattern> {
+    config
+        .redact_patterns
+        .iter()
+        .filter_map(|pattern| match Regex::new(pattern) {
+            Ok(regex) => {
+                let group = if regex.captures_len() > 1 { 1 } else { 0 };
+                Some(Pattern { kind: "custom".to_string(), regex, group })
+            },
+            Err(e) => {
+                println!("{}", format!("Invalid redact_patterns entry '{}': {}", pattern, e).red());
+                None
+            },
+        })
+        .collect()
+}
+
+/// Returns the placeholder for a secret, reusing it if the same value was seen before
+/// so the model can still tell identical values apart from different ones.
+fn placeholder_for(redactions: &mut Vec<Redaction>, kind: &str, secret: &str) -> String {
+    if let Some(existing) = redactions.iter().find(|r| r.secret == secret) {
+        return existing.placeholder.clone();
+    }
+    let placeholder = format!("[REDACTED:{}#{}]", kind, redactions.len() + 1);
+    redactions.push(Redaction { placeholder: placeholder.clone(), secret: secret.to_string() });
+    placeholder
+}
+
+/// Masks known secret formats and `redact_patterns` matches. Returns the masked text and how many
+/// secrets were replaced.
+pub fn redact(config: &Config, text: &str) -> (String, usize) {
+    if !config.redact_secrets {
+        return (text.to_string(), 0);
+    }
+
+    let mut redactions = REDACTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut count = 0;
+    let mut result = text.to_string();
+    let custom = custom_patterns(config);
+
+    for pattern in builtin_patterns().iter().chain(custom.iter()) {
+        if !pattern.regex.is_match(&result) {
+            continue;
+        }
+        result = pattern.regex.replace_all(&result, |caps: &Captures| {
+            let whole = caps.get(0).expect("match");
+            let Some(secret) = caps.get(pattern.group) else { return whole.as_str().to_string() };
+            // Settings like `MAX_TOKENS=4096` or `use_token: true` are not secrets
+            let value = secret.as_str();
+            if pattern.kind == "secret_assignment"
+                && (value.chars().all(|c| c.is_ascii_digit()) || matches!(value.to_lowercase().as_str(), "true" | "false" | "null" | "none"))
+            {
+                return whole.as_str().to_string();
+            }
+            count += 1;
+            let placeholder = placeholder_for(&mut redactions, &pattern.kind, secret.as_str());
+            let (start, end) = (secret.start() - whole.start(), secret.end() - whole.start());
+            format!("{}{}{}", &whole.as_str()[..start], placeholder, &whole.as_str()[end..])
+        }).into_owned();
+    }
+    (result, count)
+}
+
+/// Everything redacted so far in this session.
+pub fn redactions() -> Vec<Redaction> {
+    REDACTIONS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
@@ -0,0 +1,927 @@
+use std::path::{Component, Path, PathBuf};
+use crate::config::Config;
+
+#[derive(Debug, Default, Clone)]
+pub struct SimpleCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub target: String,
+    pub write: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Op(String),
+}
+
+const SEPARATORS: &[&str] = &[";", "&", "&&", "|", "||", "|&", "\n", "(", ")"];
+const WRAPPERS: &[&str] = &["sudo", "doas", "nohup", "time", "nice", "ionice", "command", "exec", "env", "stdbuf", "timeout"];
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash"];
+
+/// Commands that only inspect the system. Some are further restricted by `is_inspection_command`.
+pub const READ_ONLY_COMMANDS: &[&str] = &[
+    "ls", "ll", "dir", "cat", "tac", "head", "tail", "less", "more", "grep", "egrep", "fgrep", "rg", "zgrep", "zcat",
+    "find", "locate", "which", "whereis", "type", "file", "stat", "wc", "du", "df", "free", "uptime", "uname",
+    "hostname", "whoami", "id", "groups", "date", "cal", "ps", "pgrep", "pstree", "top", "htop", "lsof", "ss",
+    "netstat", "ip", "ifconfig", "ping", "traceroute", "tracepath", "dig", "nslookup", "host", "journalctl", "dmesg",
+    "systemctl", "lsblk", "blkid", "findmnt", "env", "printenv", "echo", "printf", "pwd", "tree", "sort", "uniq",
+    "cut", "tr", "sed", "diff", "cmp", "md5sum", "sha1sum", "sha256sum", "base64", "jq", "xxd", "hexdump", "od",
+    "strings", "nproc", "lscpu", "lsmod", "lspci", "lsusb", "lshw", "vmstat", "iostat", "mpstat", "sar", "last",
+    "lastlog", "w", "who", "getent", "test", "[", "true", "false", "basename", "dirname", "realpath", "readlink",
+    "column", "nl", "rev", "fold", "fmt", "seq", "sleep", "git", "docker", "podman", "kubectl", "apt", "dpkg", "rpm",
+];
+
+const GIT_READ_SUBCOMMANDS: &[&str] = &["status", "log", "diff", "show", "ls-files", "rev-parse", "blame", "describe", "shortlog", "reflog", "grep"];
+const GIT_LIST_SUBCOMMANDS: &[&str] = &["branch", "remote", "tag"];
+const SYSTEMCTL_READ_SUBCOMMANDS: &[&str] = &["status", "show", "cat", "list-units", "list-unit-files", "list-timers", "list-sockets", "is-active", "is-enabled", "is-failed"];
+const CONTAINER_READ_SUBCOMMANDS: &[&str] = &["ps", "images", "inspect", "logs", "top", "version", "info", "port", "diff", "stats"];
+const KUBECTL_READ_SUBCOMMANDS: &[&str] = &["get", "describe", "logs", "top", "version", "explain", "api-resources", "cluster-info"];
+const APT_READ_SUBCOMMANDS: &[&str] = &["list", "show", "policy", "search", "depends", "rdepends"];
+/// Commands that only write their last operand (or `-t DIR`); the other operands are read.
+/// `ln` is not one of them: a link to a path outside the roots would let later commands escape.
+const COPY_COMMANDS: &[&str] = &["cp", "rsync", "scp", "install"];
+const IP_WRITE_WORDS: &[&str] = &["add", "del", "delete", "deleteall", "set", "flush", "change", "replace", "append", "prepend", "exec", "restore"];
+
+fn is_operator_char(c: char) -> bool {
+    matches!(c, ';' | '&' | '|' | '<' | '>' | '(' | ')' | '\n')
+}
+
+fn tokenize(command: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                for q in chars.by_ref() {
+                    if q == '\'' { break; }
+                    word.push(q);
+                }
+            },
+            '"' => {
+                in_word = true;
+                while let Some(q) = chars.next() {
+                    match q {
+                        '"' => break,
+                        '\\' => match chars.peek() {
+                            Some(&next) if matches!(next, '"' | '\\' | '$' | '`') => { word.push(next); chars.next(); },
+                            _ => word.push('\\'),
+                        },
+                        _ => word.push(q),
+                    }
+                }
+            },
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some('\n') | None => {},
+                    Some(next) => word.push(next),
+                }
+            },
+            '#' if !in_word => {
+                while chars.peek().is_some_and(|&n| n != '\n') { chars.next(); }
+            },
+            c if is_operator_char(c) => {
+                // A word made only of digits right before a redirection is a file descriptor (`2>`)
+                let fd_prefix = matches!(c, '<' | '>') && in_word && word.chars().all(|d| d.is_ascii_digit());
+                if in_word && !fd_prefix {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                word.clear();
+                in_word = false;
+
+                let mut op = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    let candidate = format!("{}{}", op, next);
+                    if matches!(candidate.as_str(), "&&" | "||" | "|&" | ">>" | ">|" | ">&" | "&>" | "&>>" | "<<" | "<<<" | "<&" | "<>") {
+                        op = candidate;
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Op(op));
+            },
+            c if c.is_whitespace() => {
+                if in_word {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                    in_word = false;
+                }
+            },
+            _ => {
+                in_word = true;
+                word.push(c);
+            },
+        }
+    }
+    if in_word {
+        tokens.push(Token::Word(word));
+    }
+    tokens
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') && !name.starts_with(|c: char| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// The command `env` runs, as words: its options, including `-S` strings, and assignments are
+/// taken off. Empty for a bare `env`, `None` if it has an option that isn't understood.
+fn unwrap_env(args: &[String]) -> Option<Vec<String>> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        // `-S`/`--split-string` splits its value into words that are read like arguments of `env`
+        let split = |value: &str, rest: std::slice::Iter<String>| {
+            let mut words: Vec<String> = tokenize(value).into_iter().map(|t| match t { Token::Word(w) | Token::Op(w) => w }).collect();
+            words.extend(rest.cloned());
+            unwrap_env(&words)
+        };
+        if arg == "--" {
+            return Some(iter.cloned().collect());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = long.split_once('=').map_or((long, None), |(n, v)| (n, Some(v)));
+            match name {
+                "ignore-environment" | "null" | "debug" => {},
+                "unset" | "chdir" => if value.is_none() { iter.next()?; },
+                "split-string" => {
+                    let value = match value { Some(value) => value, None => iter.next()? };
+                    return split(value, iter);
+                },
+                _ => return None,
+            }
+        } else if let Some(cluster) = arg.strip_prefix('-').filter(|c| !c.is_empty()) {
+            for (i, c) in cluster.char_indices() {
+                match c {
+                    'i' | '0' | 'v' => continue,
+                    'u' | 'C' | 'S' => {
+                        let rest = &cluster[i + 1..];
+                        let value = if rest.is_empty() { iter.next()?.as_str() } else { rest };
+                        if c == 'S' {
+                            return split(value, iter);
+                        }
+                        break;
+                    },
+                    _ => return None,
+                }
+            }
+        } else if arg != "-" && !is_assignment(arg) {
+            let mut words = vec![arg.clone()];
+            words.extend(iter.cloned());
+            return Some(words);
+        }
+    }
+    Some(Vec::new())
+}
+
+fn unwrap_wrappers(mut command: SimpleCommand) -> SimpleCommand {
+    while WRAPPERS.contains(&command.program.as_str()) {
+        let wrapper = std::mem::take(&mut command.program);
+        if wrapper == "env" {
+            let args = std::mem::take(&mut command.args);
+            match unwrap_env(&args) {
+                Some(mut words) if !words.is_empty() => {
+                    command.program = words.remove(0);
+                    command.args = words;
+                    continue;
+                },
+                // A bare `env` prints the environment
+                Some(_) => command.program = wrapper,
+                // Kept whole, so the read-only check rejects it
+                None => (command.program, command.args) = (wrapper, args),
+            }
+            break;
+        }
+        let mut args = std::mem::take(&mut command.args).into_iter();
+        while let Some(arg) = args.next() {
+            if arg.starts_with('-') {
+                // Options of sudo and nice that take a separate value
+                if (wrapper == "sudo" && matches!(arg.as_str(), "-u" | "-g" | "-h" | "-p" | "-C" | "-r" | "-t" | "-U"))
+                    || (wrapper == "nice" && arg == "-n")
+                {
+                    args.next();
+                }
+                continue;
+            }
+            if wrapper == "timeout" && arg.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            command.program = arg;
+            break;
+        }
+        command.args = args.collect();
+        if command.program.is_empty() {
+            // A bare wrapper such as `env` is a command on its own
+            command.program = wrapper;
+            break;
+        }
+    }
+    command
+}
+
+/// Splits a shell command line into simple commands, following pipes, lists, subshells and `sh -c` scripts.
+pub fn parse_command(command: &str) -> Vec<SimpleCommand> {
+    let mut commands = Vec::new();
+    let mut current = SimpleCommand::default();
+    let mut tokens = tokenize(command).into_iter();
+
+    let finish = |current: &mut SimpleCommand, commands: &mut Vec<SimpleCommand>| {
+        let command = unwrap_wrappers(std::mem::take(current));
+        if command.program.is_empty() && command.redirects.is_empty() {
+            return;
+        }
+        if SHELLS.contains(&command.program.as_str()) {
+            if let Some(pos) = command.args.iter().position(|a| a == "-c") {
+                if let Some(script) = command.args.get(pos + 1) {
+                    commands.extend(parse_command(script));
+                    return;
+                }
+            }
+        }
+        commands.push(command);
+    };
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Op(op) if SEPARATORS.contains(&op.as_str()) => finish(&mut current, &mut commands),
+            Token::Op(op) => {
+                let Some(Token::Word(target)) = tokens.next() else { continue };
+                let write = op.contains('>') && !(op.ends_with('&') && target.chars().all(|c| c.is_ascii_digit() || c == '-'));
+                let read = op.contains('<') && !op.starts_with("<<") && !op.ends_with('&');
+                if write || read {
+                    current.redirects.push(Redirect { target, write });
+                }
+            },
+            Token::Word(word) => {
+                if current.program.is_empty() && current.args.is_empty() && is_assignment(&word) {
+                    continue;
+                }
+                if current.program.is_empty() {
+                    current.program = word;
+                } else {
+                    current.args.push(word);
+                }
+            },
+        }
+    }
+    finish(&mut current, &mut commands);
+    commands
+}
+
+/// True when the command line uses command substitution, whose effects can't be judged statically.
+pub fn has_substitution(command: &str) -> bool {
+    command.contains("$(") || command.contains('`') || command.contains("<(") || command.contains(">(")
+}
+
+fn program_name(program: &str) -> &str {
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+fn first_operand(args: &[String]) -> Option<&str> {
+    args.iter().map(|a| a.as_str()).find(|a| !a.starts_with('-'))
+}
+
+/// Checks one simple command against the inspection allowlist, including subcommand and flag rules.
+pub fn is_inspection_command(command: &SimpleCommand, extra_allowed: &[String]) -> bool {
+    let program = program_name(&command.program);
+    if command.redirects.iter().any(|r| r.write && r.target != "/dev/null") {
+        return false;
+    }
+    if extra_allowed.iter().any(|allowed| allowed == program) {
+        return true;
+    }
+    if !READ_ONLY_COMMANDS.contains(&program) {
+        return false;
+    }
+
+    let args = &command.args;
+    let has_arg = |names: &[&str]| args.iter().any(|a| names.iter().any(|n| a == n || a.starts_with(&format!("{}=", n))));
+    match program {
+        "find" => !has_arg(&["-delete", "-exec", "-execdir", "-ok", "-okdir", "-fprint", "-fprint0", "-fprintf", "-fls"]),
+        "sed" => is_printing_sed(args),
+        "sort" => !has_arg(&["--output", "--compress-program"]) && !args.iter().any(|a| has_short_flag(a, 'o', "ktST")),
+        "tree" => !args.iter().any(|a| a == "-o" || a.starts_with("--output")),
+        // `uniq IN OUT` and `xxd [-r] IN OUT` write their second operand
+        "uniq" => operands(args, &["-f", "-s", "-w", "--skip-fields", "--skip-chars", "--check-chars"]).len() <= 1,
+        "xxd" => operands(args, &["-c", "-cols", "-g", "-groupsize", "-l", "-len", "-s", "-seek", "-o", "-offset", "-n", "-name"]).len() <= 1,
+        "date" => !has_arg(&["-s", "--set"]),
+        // `-F`/`--file` sets the name from a file
+        "hostname" => args.iter().all(|a| a.starts_with('-') && !is_long_option(a, "--file", 4) && !has_short_flag(a, 'F', "")),
+        // `--pre` runs a program on every file searched
+        "rg" => !has_arg(&["--pre", "--pre-glob"]),
+        // `-o`/`-O`/`--log-file` copy the input to a file
+        "less" => !args.iter().any(|a| {
+            is_long_option(a, "--log-file", 5) || is_long_option(a, "--LOG-FILE", 5)
+                || has_short_flag(a, 'o', "bhjkpPtTxyz#") || has_short_flag(a, 'O', "bhjkpPtTxyz#")
+        }),
+        "journalctl" => !args.iter().any(|a| a.starts_with("--vacuum") || a == "--rotate" || a == "--flush" || a == "--sync"),
+        "dmesg" => !args.iter().any(|a| a == "-c" || a == "-C" || a == "-D" || a == "--clear" || a == "--read-clear"),
+        // `-batch`/`-b` runs the commands in a file; options may be abbreviated and have two dashes
+        "ip" => !args.iter().any(|a| {
+            let option = a.strip_prefix('-').map(|o| o.strip_prefix('-').unwrap_or(o));
+            IP_WRITE_WORDS.contains(&a.as_str()) || option.is_some_and(|o| o == "b" || (o.len() >= 2 && "batch".starts_with(o)))
+        }),
+        "ifconfig" => args.len() <= 1,
+        "env" => args.is_empty(),
+        "printenv" => true,
+        "git" => match first_operand(args) {
+            // `-O`/`--open-files-in-pager` runs a program on the matching files
+            Some("grep") => !args.iter().any(|a| is_long_option(a, "--open-files-in-pager", 4) || has_short_flag(a, 'O', "efABCm")),
+            Some("reflog") => !args.iter().any(|a| matches!(a.as_str(), "expire" | "delete" | "drop")),
+            // `--output=FILE` writes the diff to a file, `--ext-diff` runs the configured diff program
+            Some(sub) if GIT_READ_SUBCOMMANDS.contains(&sub) => {
+                !args.iter().any(|a| is_long_option(a, "--output", 4) || is_long_option(a, "--ext-diff", 5))
+            },
+            Some("stash") => {
+                let rest: Vec<&String> = args.iter().skip_while(|a| a.as_str() != "stash").skip(1).collect();
+                rest.first().is_some_and(|a| a.as_str() == "list" || a.as_str() == "show")
+            },
+            Some(sub) if GIT_LIST_SUBCOMMANDS.contains(&sub) => {
+                let rest: Vec<&String> = args.iter().skip_while(|a| a.as_str() != sub).skip(1).collect();
+                // Deleting, moving, copying, forcing, upstream changes and the description editor
+                let writes = |a: &str| {
+                    "dDmMcCfu".chars().any(|flag| has_short_flag(a, flag, ""))
+                        || ["--delete", "--move", "--copy", "--force", "--edit-description", "--unset-upstream", "--set-upstream-to"]
+                            .iter()
+                            .any(|name| is_long_option(a, name, 5))
+                };
+                rest.iter().all(|a| a.starts_with('-') || a.as_str() == "list" || a.as_str() == "show")
+                    && !rest.iter().any(|a| writes(a))
+            },
+            _ => false,
+        },
+        "systemctl" => first_operand(args).is_some_and(|sub| SYSTEMCTL_READ_SUBCOMMANDS.contains(&sub)),
+        "docker" | "podman" => first_operand(args).is_some_and(|sub| CONTAINER_READ_SUBCOMMANDS.contains(&sub))
+            && (first_operand(args) != Some("stats") || has_arg(&["--no-stream"])),
+        "kubectl" => first_operand(args).is_some_and(|sub| KUBECTL_READ_SUBCOMMANDS.contains(&sub)),
+        "apt" => first_operand(args).is_some_and(|sub| APT_READ_SUBCOMMANDS.contains(&sub)),
+        "dpkg" => args.iter().all(|a| matches!(a.as_str(), "-l" | "-L" | "-s" | "-S" | "-p" | "--list" | "--listfiles" | "--status" | "--search" | "--print-avail") || !a.starts_with('-')),
+        "rpm" => args.first().is_some_and(|a| a.starts_with("-q") || a == "--query"),
+        _ => true,
+    }
+}
+
+/// Whether `arg` is the long option `name`, possibly with `=value` or abbreviated to at least
+/// `min_len` characters, as git and GNU tools accept.
+fn is_long_option(arg: &str, name: &str, min_len: usize) -> bool {
+    let given = arg.split('=').next().unwrap_or(arg);
+    given.len() >= min_len && name.starts_with(given)
+}
+
+/// Whether a cluster of short options such as `-nro` contains `flag`. The cluster ends at the
+/// first of `value_flags`, whose value is the rest of the argument.
+fn has_short_flag(arg: &str, flag: char, value_flags: &str) -> bool {
+    let Some(cluster) = arg.strip_prefix('-').filter(|c| !c.is_empty() && !c.starts_with('-')) else { return false };
+    for c in cluster.chars() {
+        if c == flag {
+            return true;
+        }
+        if value_flags.contains(c) {
+            return false;
+        }
+    }
+    false
+}
+
+/// The operands of a command: everything but options and the values of `value_options`.
+fn operands<'a>(args: &'a [String], value_options: &[&str]) -> Vec<&'a str> {
+    let mut operands = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            operands.extend(iter.map(String::as_str));
+            break;
+        }
+        if value_options.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with('-') || arg == "-" {
+            operands.push(arg.as_str());
+        }
+    }
+    operands
+}
+
+/// Whether a sed invocation only prints: no in-place editing, no script files (which can't be
+/// checked), and only scripts that `is_printing_sed_script` accepts.
+fn is_printing_sed(args: &[String]) -> bool {
+    let mut scripts = Vec::new();
+    let mut operands = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            operands.extend(iter.by_ref().cloned());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = long.split_once('=').map_or((long, None), |(n, v)| (n, Some(v.to_string())));
+            match name {
+                "expression" => match value.or_else(|| iter.next().cloned()) {
+                    Some(script) => scripts.push(script),
+                    None => return false,
+                },
+                "line-length" => if value.is_none() { iter.next(); },
+                "quiet" | "silent" | "regexp-extended" | "posix" | "separate" | "unbuffered" | "null-data" | "debug" | "sandbox" => {},
+                // --in-place, --file and anything unknown
+                _ => return false,
+            }
+        } else if let Some(cluster) = arg.strip_prefix('-').filter(|c| !c.is_empty()) {
+            for (i, c) in cluster.char_indices() {
+                match c {
+                    'n' | 'r' | 'E' | 's' | 'u' | 'z' => continue,
+                    'e' | 'l' => {
+                        let rest = &cluster[i + 1..];
+                        let value = if rest.is_empty() { iter.next().cloned() } else { Some(rest.to_string()) };
+                        match (c, value) {
+                            ('e', Some(script)) => scripts.push(script),
+                            ('l', Some(_)) => {},
+                            _ => return false,
+                        }
+                        break;
+                    },
+                    // -i, -f and anything unknown
+                    _ => return false,
+                }
+            }
+        } else {
+            operands.push(arg.clone());
+        }
+    }
+    if scripts.is_empty() {
+        // Without -e the first operand is the script
+        match operands.first() {
+            Some(script) => scripts.push(script.clone()),
+            None => return true,
+        }
+    }
+    scripts.iter().all(|script| is_printing_sed_script(script))
+}
+
+/// Walks a sed script command by command and rejects the ones that read or write files or run
+/// commands: `r`, `R`, `w`, `W`, `e` and the `e` and `w` flags of `s`. Anything it doesn't
+/// understand is rejected too.
+fn is_printing_sed_script(script: &str) -> bool {
+    let chars: Vec<char> = script.chars().collect();
+    let mut i = 0;
+    // The end of a `/.../`-delimited part, honouring escapes and, in regexes, bracket expressions
+    let delimited = |mut i: usize, delimiter: char, regex: bool| -> Option<usize> {
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => i += 2,
+                '[' if regex => {
+                    i += 1;
+                    if chars.get(i) == Some(&'^') { i += 1; }
+                    if chars.get(i) == Some(&']') { i += 1; }
+                    while i < chars.len() && chars[i] != ']' {
+                        // `[:alpha:]`, `[.-.]` and `[=a=]` inside the brackets
+                        match (chars[i], chars.get(i + 1)) {
+                            ('[', Some(&kind)) if matches!(kind, ':' | '.' | '=') => {
+                                i += 2;
+                                while i + 1 < chars.len() && !(chars[i] == kind && chars[i + 1] == ']') { i += 1; }
+                                i += 2;
+                            },
+                            _ => i += 1,
+                        }
+                    }
+                    i += 1;
+                },
+                c if c == delimiter => return Some(i + 1),
+                _ => i += 1,
+            }
+        }
+        None
+    };
+    let to_line_end = |i: usize| chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |p| i + p);
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() || c == ';' || c == '}' || c == '!' {
+            i += 1;
+            continue;
+        }
+        // Addresses: line numbers, `$`, `/regex/`, `\cregexc`, ranges and steps
+        if c.is_ascii_digit() || matches!(c, '$' | ',' | '~' | '+') {
+            i += 1;
+            continue;
+        }
+        if c == '/' || c == '\\' {
+            let (start, delimiter) = if c == '/' { (i + 1, '/') } else { (i + 2, *chars.get(i + 1).unwrap_or(&'/')) };
+            let Some(end) = delimited(start, delimiter, true) else { return false };
+            i = end;
+            while matches!(chars.get(i), Some('I' | 'M')) { i += 1; }
+            continue;
+        }
+        i += 1;
+        match c {
+            '{' | '=' | 'd' | 'D' | 'g' | 'G' | 'h' | 'H' | 'n' | 'N' | 'p' | 'P' | 'x' | 'z' | 'F' => {},
+            'l' | 'L' | 'q' | 'Q' => while chars.get(i).is_some_and(|c| c.is_ascii_digit() || *c == ' ') { i += 1; },
+            '#' | 'a' | 'i' | 'c' => i = to_line_end(i),
+            // Labels end at a `;` for GNU sed; stopping there at the latest checks more, not less
+            ':' | 'b' | 't' | 'T' | 'v' => while chars.get(i).is_some_and(|c| *c != ';' && *c != '\n' && *c != '}') { i += 1; },
+            's' | 'y' => {
+                let Some(&delimiter) = chars.get(i) else { return false };
+                if delimiter == '\n' || delimiter == '\\' {
+                    return false;
+                }
+                let Some(middle) = delimited(i + 1, delimiter, c == 's') else { return false };
+                let Some(end) = delimited(middle, delimiter, false) else { return false };
+                i = end;
+                if c == 's' {
+                    while let Some(&flag) = chars.get(i) {
+                        match flag {
+                            'g' | 'p' | 'i' | 'I' | 'm' | 'M' => i += 1,
+                            f if f.is_ascii_digit() => i += 1,
+                            ';' | '\n' | '}' | ' ' | '\t' => break,
+                            // `e`, `w` and anything unknown
+                            _ => return false,
+                        }
+                    }
+                }
+            },
+            // `e`, `r`, `R`, `w`, `W` and anything unknown
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// True when every part of the command line only reads system state.
+pub fn is_read_only(command: &str) -> bool {
+    if has_substitution(command) {
+        return false;
+    }
+    let commands = parse_command(command);
+    !commands.is_empty() && commands.iter().all(|c| is_inspection_command(c, &[]))
+}
+
+/// Why a command may not run in read-only mode, or `None` if it only inspects the system.
+pub fn read_only_violation(command: &str, extra_allowed: &[String]) -> Option<String> {
+    if has_substitution(command) {
+        return Some("command substitution is not allowed".to_string());
+    }
+    let commands = parse_command(command);
+    for simple in &commands {
+        if matches!(program_name(&simple.program), "cd" | "pushd" | "popd") {
+            continue;
+        }
+        if simple.redirects.iter().any(|r| r.write && r.target != "/dev/null") {
+            return Some("redirecting output to a file is not allowed".to_string());
+        }
+        if !is_inspection_command(simple, extra_allowed) {
+            return Some(format!("`{}` is not an allowed inspection command", command_summary(simple)));
+        }
+    }
+    None
+}
+
+/// The program and, for tools with subcommands, the subcommand, e.g. `git push`.
+fn command_summary(command: &SimpleCommand) -> String {
+    let program = program_name(&command.program);
+    match (program, first_operand(&command.args)) {
+        ("git" | "systemctl" | "docker" | "podman" | "kubectl" | "apt", Some(sub)) => format!("{} {}", program, sub),
+        _ => program.to_string(),
+    }
+}
+
+pub fn expand_path(path: &str, cwd: &Path) -> PathBuf {
+    if path == "~" {
+        return dirs::home_dir().unwrap_or_else(|| PathBuf::from(path));
+    }
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    cwd.join(path)
+}
+
+fn is_virtual_path(path: &Path) -> bool {
+    ["/dev", "/proc", "/sys"].iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// Paths a modifying command is likely to touch: its existing path operands, plus write redirection
+/// targets and not-yet-existing operands in an existing directory (things the command may create).
+pub fn predicted_paths(command: &str, cwd: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut cwd = cwd.to_path_buf();
+    for simple in parse_command(command) {
+        if matches!(program_name(&simple.program), "cd" | "pushd") {
+            if let Some(target) = expand_vars(first_operand(&simple.args).unwrap_or("~")) {
+                cwd = expand_path(&target, &cwd);
+            }
+            continue;
+        }
+        if is_inspection_command(&simple, &[]) {
+            continue;
+        }
+        let operands = simple.args.iter().filter(|a| !a.starts_with('-') && !a.contains("://"));
+        let targets = simple.redirects.iter().filter(|r| r.write).map(|r| &r.target);
+        for arg in operands.chain(targets) {
+            if arg.is_empty() || arg.contains(['*', '?', '[']) {
+                continue;
+            }
+            let path = expand_path(arg, &cwd);
+            if is_virtual_path(&path) || paths.contains(&path) {
+                continue;
+            }
+            let exists = path.symlink_metadata().is_ok();
+            if exists || path.parent().is_some_and(|p| p.is_dir()) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+/// Removes `.` and `..` components without touching the filesystem.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => { normalized.pop(); },
+            Component::CurDir => {},
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Resolves `..` and symlinks for as much of the path as exists; the rest is appended as written.
+pub fn resolve_path(path: &Path) -> PathBuf {
+    let normalized = normalize_lexically(path);
+    let mut existing = normalized.as_path();
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing.iter().rev().fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name.to_os_string());
+                existing = parent;
+            },
+            _ => return normalized,
+        }
+    }
+}
+
+/// Expands `$VAR` and `${VAR}` from aiosc's environment. `None` if a variable is unset.
+pub fn expand_vars(word: &str) -> Option<String> {
+    let mut result = String::new();
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        let braced = chars.next_if_eq(&'{').is_some();
+        let mut name = String::new();
+        while let Some(n) = chars.next_if(|n| n.is_ascii_alphanumeric() || *n == '_') {
+            name.push(n);
+        }
+        if braced && chars.next() != Some('}') {
+            return None;
+        }
+        if name.is_empty() {
+            if braced {
+                return None;
+            }
+            result.push('$');
+            continue;
+        }
+        result.push_str(&std::env::var(&name).ok()?);
+    }
+    Some(result)
+}
+
+/// Arguments of a simple command that name paths it may write to.
+fn written_operands(command: &SimpleCommand) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut target_dir = None;
+    let mut args = command.args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-t" || arg == "--target-directory" {
+            target_dir = args.next().cloned();
+        } else if let Some(dir) = arg.strip_prefix("--target-directory=") {
+            target_dir = Some(dir.to_string());
+        } else if let Some(output) = arg.strip_prefix("of=").filter(|_| program_name(&command.program) == "dd") {
+            operands.push(output.to_string());
+        } else if program_name(&command.program) == "dd" {
+            continue;
+        } else if let Some((_, value)) = arg.split_once('=').filter(|_| arg.starts_with("--")) {
+            if value.contains('/') {
+                operands.push(value.to_string());
+            }
+        } else if !arg.starts_with('-') && !arg.contains("://") {
+            operands.push(arg.clone());
+        }
+    }
+    if COPY_COMMANDS.contains(&program_name(&command.program)) {
+        // Remote `host:path` operands of scp and rsync are not local paths
+        operands.retain(|o| !o.split('/').next().is_some_and(|first| first.contains(':')));
+        return target_dir.or_else(|| operands.pop()).into_iter().collect();
+    }
+    operands.extend(target_dir);
+    operands
+}
+
+fn is_harmless_device(path: &Path) -> bool {
+    ["/dev/null", "/dev/stdout", "/dev/stderr", "/dev/tty"].iter().any(|p| path == Path::new(p)) || path.starts_with("/dev/fd")
+}
+
+/// Write targets of a command that fall outside `roots`, as they were written in the command.
+/// Targets that can't be determined statically (command substitution, unset variables) count as outside.
+pub fn paths_outside_roots(command: &str, cwd: &Path, roots: &[PathBuf]) -> Vec<String> {
+    if has_substitution(command) {
+        return vec!["command substitution".to_string()];
+    }
+    let mut outside = Vec::new();
+    let mut cwd = cwd.to_path_buf();
+    for simple in parse_command(command) {
+        // Relative paths after a `cd` in the same command line are relative to its target
+        // Changing into a directory outside the roots counts as leaving the jail too
+        if matches!(program_name(&simple.program), "cd" | "pushd") {
+            let target = first_operand(&simple.args).unwrap_or("~");
+            match expand_vars(target) {
+                Some(expanded) => {
+                    cwd = resolve_path(&expand_path(&expanded, &cwd));
+                    if !roots.iter().any(|root| cwd.starts_with(root)) {
+                        outside.push(target.to_string());
+                    }
+                },
+                None => outside.push(target.to_string()),
+            }
+            continue;
+        }
+        let targets = simple.redirects.iter().filter(|r| r.write).map(|r| r.target.clone());
+        let operands = if is_inspection_command(&simple, &[]) { Vec::new() } else { written_operands(&simple) };
+        for arg in operands.into_iter().chain(targets) {
+            if arg.is_empty() || outside.contains(&arg) {
+                continue;
+            }
+            let Some(expanded) = expand_vars(&arg) else {
+                outside.push(arg);
+                continue;
+            };
+            // A glob can only match below its first literal directory
+            let literal: PathBuf = Path::new(&expanded)
+                .components()
+                .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+                .collect();
+            let path = resolve_path(&expand_path(&literal.to_string_lossy(), &cwd));
+            if !is_harmless_device(&path) && !roots.iter().any(|root| path.starts_with(root)) {
+                outside.push(arg);
+            }
+        }
+    }
+    outside
+}
+
+pub fn path_jail_mode(config: &Config) -> Option<&'static str> {
+    match config.path_jail.to_lowercase().as_str() {
+        "deny" | "on" | "true" => Some("deny"),
+        "confirm" => Some("confirm"),
+        _ => None,
+    }
+}
+
+/// Paths the command would write outside `allowed_roots`, or nothing when the jail is off.
+pub fn jail_violations(config: &Config, command: &str) -> Vec<String> {
+    if path_jail_mode(config).is_none() {
+        return Vec::new();
+    }
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let roots: Vec<PathBuf> = config.allowed_roots.iter().map(PathBuf::from).collect();
+    paths_outside_roots(command, &cwd, &roots)
+}
+
+/// Describes the path jail for the system prompt.
+pub fn jail_prompt_note(config: &Config) -> Option<String> {
+    let consequence = match path_jail_mode(config)? {
+        "deny" => "Commands writing or changing directory anywhere else are refused",
+        _ => "Commands writing or changing directory anywhere else need the user's approval",
+    };
+    Some(format!(
+        "- Only write inside: {}. {}; command substitution and unset variables in paths count as elsewhere.",
+        config.allowed_roots.join(", "),
+        consequence
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sed_is_read_only_only_while_it_prints() {
+        for command in ["sed -n '1,5p' f", "sed 's/a/b/g' f", "sed -e p -e '$d' f", "sed -n '/[/]w/p' f", "sed ':a;N;$!ba;s/\\n/ /g' f", "sed --expression=p f"] {
+            assert!(is_read_only(command), "{}", command);
+        }
+        for command in [
+            "sed -n '1e rm -rf ~' f",
+            "sed 's/a/b/w /tmp/x' f",
+            "sed 's/a/b/e' f",
+            "sed -n 'w out' f",
+            "sed '1r /tmp/secret' f",
+            "sed 'W out' f",
+            "sed -e p -e 'w out' f",
+            "sed --expression='w out' f",
+            "sed --expression 'w out' f",
+            "sed -f script.sed f",
+            "sed --file=script.sed f",
+            "sed ':a;w x' f",
+            "sed 's/a/[/;w out;s/b/]/g' f",
+            "sed -i 's/a/b/' f",
+            "sed -ni p f",
+        ] {
+            assert!(!is_read_only(command), "{}", command);
+            assert!(read_only_violation(command, &[]).is_some(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn output_operands_and_program_options_are_not_read_only() {
+        for command in ["uniq in", "uniq -c -f 1 in", "xxd f", "xxd -c 16 f", "sort -k 2 f", "git log --oneline", "git grep -n foo", "git diff HEAD~1", "du -sh /tmp/jt"] {
+            assert!(is_read_only(command), "{}", command);
+        }
+        for command in [
+            "uniq in out",
+            "xxd in out",
+            "xxd -r in out",
+            "sort --compress-program=sh f",
+            "sort -o out f",
+            "sort -uo out f",
+            "git log --output=x",
+            "git diff --outp=x",
+            "git show --ext-diff",
+            "git grep -O foo",
+            "git grep -Ovim foo",
+            "git grep --open-files-in-pager=vim foo",
+            "git reflog expire --all",
+            "rg --pre ./evil x",
+            "rg --pre=sh x",
+            "ip netns exec foo rm -rf x",
+            "ip -batch cmds",
+            "ip -b cmds",
+            "ip addr restore",
+            "hostname --file=/tmp/jt/h",
+            "hostname -F/tmp/jt/h",
+            "less -o out f",
+            "less -Oout f",
+            "less --log-file=out f",
+            "less --LOG-FILE out f",
+            "git stash",
+            "git stash -u",
+            "git stash drop",
+            "git branch --edit-description",
+            "git branch --unset-upstream",
+            "git branch --set-upstream-to=origin/main",
+            "git branch -u origin/main",
+            "git branch -c x",
+            "git branch -f x",
+            "git tag -f x",
+        ] {
+            assert!(!is_read_only(command), "{}", command);
+            assert!(read_only_violation(command, &[]).is_some(), "{}", command);
+        }
+        for command in ["rg -n foo", "ip -br addr", "ip netns list", "hostname -f", "less -N f", "git stash list", "git stash show -p", "git branch -vv", "git tag -l"] {
+            assert!(is_read_only(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn env_runs_the_command_in_its_arguments() {
+        for command in ["env", "env -i FOO=1", "printenv PATH", "env FOO=1 ls", "env -u HOME ls", "env -S 'ls -l'"] {
+            assert!(is_read_only(command), "{}", command);
+        }
+        for command in [
+            "env --split-string='rm -rf x'",
+            "env --split-string 'rm -rf x'",
+            "env -Stouch\\ x",
+            "env -S 'FOO=1 touch x'",
+            "env -iS 'touch x'",
+            "env -C /tmp/jt touch x",
+            "env --chdir=/tmp/jt touch x",
+            "env --unknown",
+            "env -- touch x",
+        ] {
+            assert!(!is_read_only(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn the_jail_sees_through_env() {
+        let (cwd, roots) = (Path::new("/tmp/jt"), [PathBuf::from("/tmp/jt")]);
+        for command in ["rm -rf /srv/x", "env --split-string='rm -rf /srv/x'", "env -Srm\\ /srv/x", "env -i FOO=1 rm /srv/x", "sudo env -S 'rm /srv/x'"] {
+            assert_eq!(paths_outside_roots(command, cwd, &roots), ["/srv/x"], "{}", command);
+        }
+        assert!(paths_outside_roots("env -S 'rm /tmp/jt/x'", cwd, &roots).is_empty());
+    }
+}
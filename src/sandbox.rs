@@ -0,0 +1,244 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::Permissions;
+use std::hash::{Hash, Hasher};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use crate::{config::Config, safety};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    Bwrap,
+    Unshare,
+}
+
+// Runs inside the new namespaces: overlay/tmpfs the working directory, remount everything
+// else read-only, give the command a private /tmp, then exec the shell.
+const UNSHARE_SCRIPT: &str = r#"set -e
+cwd="$1"; mode="$2"; upper="$3"; work="$4"; shift 4
+case "$mode" in
+  overlay) mount -t overlay aiosc-overlay -o "lowerdir=$cwd,upperdir=$upper,workdir=$work" "$cwd" ;;
+  tmpfs) mount -t tmpfs aiosc-tmpfs "$cwd" ;;
+esac
+awk '{print $2}' /proc/self/mounts | while read -r m; do
+  [ "$mode" != readonly ] && [ "$m" = "$cwd" ] && continue
+  mount -o remount,bind,ro "$m" 2>/dev/null || true
+done
+case "$cwd" in
+  /tmp|/tmp/*) ;;
+  *) mount -t tmpfs aiosc-tmp /tmp ;;
+esac
+cd "$cwd"
+exec "$@"
+"#;
+
+// Path jail: keep the allowed roots writable as bind mounts, remount everything else read-only.
+// Unlike the sandbox, writes inside the roots reach the real files and network stays available.
+const JAIL_SCRIPT: &str = r#"set -e
+cwd="$1"; shift
+roots=
+while [ "$1" != -- ]; do
+  mount --bind "$1" "$1"
+  roots="$roots$1
+"
+  shift
+done
+shift
+awk '{print $2}' /proc/self/mounts | while read -r m; do
+  printf '%s' "$roots" | grep -qxF "$m" && continue
+  mount -o remount,bind,ro "$m" 2>/dev/null || true
+done
+cd "$cwd"
+exec "$@"
+"#;
+
+pub fn is_enabled(config: &Config) -> bool {
+    !matches!(config.sandbox.to_lowercase().as_str(), "" | "off" | "false" | "none")
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+pub fn resolve_backend(config: &Config) -> Result<Backend, String> {
+    resolve_named_backend(&config.sandbox)
+}
+
+fn resolve_named_backend(name: &str) -> Result<Backend, String> {
+    match name.to_lowercase().as_str() {
+        "bwrap" | "bubblewrap" => find_in_path("bwrap")
+            .map(|_| Backend::Bwrap)
+            .ok_or_else(|| "sandbox backend 'bwrap' is not installed".to_string()),
+        "unshare" => find_in_path("unshare")
+            .map(|_| Backend::Unshare)
+            .ok_or_else(|| "sandbox backend 'unshare' is not installed".to_string()),
+        "auto" | "on" | "true" => find_in_path("bwrap")
+            .map(|_| Backend::Bwrap)
+            .or_else(|| find_in_path("unshare").map(|_| Backend::Unshare))
+            .ok_or_else(|| "no sandbox backend found (install bubblewrap or util-linux unshare)".to_string()),
+        other => Err(format!("unknown sandbox backend '{}' (use off, auto, bwrap or unshare)", other)),
+    }
+}
+
+fn workdir_mode(config: &Config) -> &'static str {
+    match config.sandbox_workdir.to_lowercase().as_str() {
+        "tmpfs" => "tmpfs",
+        "readonly" | "ro" => "readonly",
+        _ => "overlay",
+    }
+}
+
+/// Per-session scratch directory holding the overlay upper layer, so changes made inside the
+/// sandbox survive between commands of the same session but never reach the real files.
+static SESSION_DIR: Mutex<Option<tempfile::TempDir>> = Mutex::new(None);
+
+/// Creates the session directory on first use with a random name and mode 0700, so other users
+/// can't plant files in the sandbox's view or read what commands write there.
+fn session_dir() -> Result<PathBuf, String> {
+    let mut dir = SESSION_DIR.lock().unwrap_or_else(|e| e.into_inner());
+    if dir.is_none() {
+        let created = tempfile::Builder::new()
+            .prefix("aiosc-sandbox-")
+            .permissions(Permissions::from_mode(0o700))
+            .tempdir()
+            .map_err(|e| format!("failed to create the sandbox directory: {}", e))?;
+        *dir = Some(created);
+    }
+    Ok(dir.as_ref().map(|dir| dir.path().to_path_buf()).unwrap_or_default())
+}
+
+/// Each working directory gets its own layer, since the model can `cd` between commands.
+fn overlay_dirs(cwd: &Path) -> Result<(PathBuf, PathBuf), String> {
+    let mut hasher = DefaultHasher::new();
+    cwd.hash(&mut hasher);
+    let dir = session_dir()?.join(format!("{:016x}", hasher.finish()));
+    let (upper, work) = (dir.join("upper"), dir.join("work"));
+    for path in [&upper, &work] {
+        std::fs::create_dir_all(path).map_err(|e| format!("failed to create '{}': {}", path.display(), e))?;
+    }
+    Ok((upper, work))
+}
+
+fn is_under_tmp(path: &Path) -> bool {
+    path.starts_with("/tmp")
+}
+
+pub fn build_command(config: &Config, cwd: &Path, shell: &str, shell_arg: &str, command: &str) -> Result<Command, String> {
+    let backend = resolve_backend(config)?;
+    let mode = workdir_mode(config);
+    let (upper, work) = if mode == "overlay" { overlay_dirs(cwd)? } else { (PathBuf::new(), PathBuf::new()) };
+
+    let mut cmd;
+    match backend {
+        Backend::Bwrap => {
+            cmd = Command::new("bwrap");
+            cmd.args(["--die-with-parent", "--unshare-user", "--unshare-pid", "--unshare-ipc", "--unshare-uts"]);
+            if !config.sandbox_network {
+                cmd.arg("--unshare-net");
+            }
+            cmd.args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"]);
+            if !is_under_tmp(cwd) {
+                cmd.args(["--tmpfs", "/tmp"]);
+            }
+            match mode {
+                "overlay" => {
+                    cmd.arg("--overlay-src").arg(cwd).arg("--overlay").arg(&upper).arg(&work).arg(cwd);
+                },
+                "tmpfs" => {
+                    cmd.arg("--tmpfs").arg(cwd);
+                },
+                _ => {},
+            }
+            cmd.arg("--chdir").arg(cwd).arg("--");
+        },
+        Backend::Unshare => {
+            cmd = Command::new("unshare");
+            cmd.args(["--user", "--map-root-user", "--mount", "--pid", "--fork", "--mount-proc"]);
+            if !config.sandbox_network {
+                cmd.arg("--net");
+            }
+            cmd.args(["--", "sh", "-c", UNSHARE_SCRIPT, "aiosc-sandbox"])
+                .arg(cwd).arg(mode).arg(&upper).arg(&work);
+        },
+    }
+    cmd.arg(shell).arg(shell_arg).arg(command);
+    Ok(cmd)
+}
+
+/// Whether AI commands run in the path jail sandbox. The regular sandbox already keeps everything
+/// outside the working directory read-only, so it takes precedence.
+pub fn jail_enabled(config: &Config) -> bool {
+    config.path_jail_sandbox && safety::path_jail_mode(config).is_some() && !is_enabled(config)
+}
+
+pub fn build_jail_command(config: &Config, cwd: &Path, shell: &str, shell_arg: &str, command: &str) -> Result<Command, String> {
+    let backend = resolve_named_backend("auto")?;
+    let roots: Vec<&String> = config.allowed_roots.iter().filter(|root| Path::new(root).is_dir()).collect();
+
+    let mut cmd;
+    match backend {
+        Backend::Bwrap => {
+            cmd = Command::new("bwrap");
+            cmd.args(["--die-with-parent", "--unshare-user", "--ro-bind", "/", "/", "--dev-bind", "/dev", "/dev"]);
+            for root in roots {
+                cmd.arg("--bind").arg(root).arg(root);
+            }
+            cmd.arg("--chdir").arg(cwd).arg("--");
+        },
+        Backend::Unshare => {
+            cmd = Command::new("unshare");
+            cmd.args(["--user", "--map-root-user", "--mount", "--", "sh", "-c", JAIL_SCRIPT, "aiosc-jail"]).arg(cwd);
+            cmd.args(roots).arg("--");
+        },
+    }
+    cmd.arg(shell).arg(shell_arg).arg(command);
+    Ok(cmd)
+}
+
+/// Explains a failure that looks like it was caused by the sandbox, so the model doesn't retry blindly.
+pub fn explain_denial(config: &Config, output: &str) -> Option<String> {
+    let reason = if output.contains("Read-only file system") && jail_enabled(config) {
+        "writing outside the allowed roots is not permitted"
+    } else if jail_enabled(config) {
+        return None;
+    } else if output.contains("Read-only file system") {
+        "writing outside the working directory is not permitted"
+    } else if !config.sandbox_network && [
+        "Network is unreachable",
+        "Temporary failure in name resolution",
+        "Could not resolve host",
+        "Name or service not known",
+    ].iter().any(|pattern| output.contains(pattern)) {
+        "network access is disabled"
+    } else if output.contains("Operation not permitted") {
+        "the operation requires privileges the sandbox does not grant"
+    } else {
+        return None;
+    };
+    Some(format!("Sandbox: this command was denied by the aiosc sandbox ({}). Do not retry it; report the limitation to the user instead.", reason))
+}
+
+/// Describes the sandbox for the system prompt.
+pub fn prompt_note(config: &Config) -> Option<String> {
+    if !is_enabled(config) {
+        return None;
+    }
+    let workdir = match workdir_mode(config) {
+        "tmpfs" => "the working directory is an empty scratch area",
+        "readonly" => "the working directory is read-only too",
+        _ => "changes in the working directory go to a throwaway layer and do not modify the real files",
+    };
+    Some(format!(
+        "- Commands run inside a sandbox: the filesystem is read-only, {}, /tmp is private, network is {}.",
+        workdir,
+        if config.sandbox_network { "enabled" } else { "disabled" }
+    ))
+}
+
+pub fn cleanup() {
+    SESSION_DIR.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::BorrowedFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use nix::poll::{poll, PollFd, PollFlags};
+use ptyprocess::{PtyProcess, stream::Stream};
+use crate::{config::Config, executor, interrupt, limits, sandbox};
+
+/// Variables that change on their own or are about the shell itself, not worth reporting.
+const IGNORED_VARIABLES: &[&str] = &["_", "PWD", "OLDPWD", "SHLVL", "COLUMNS", "LINES"];
+
+struct Session {
+    process: PtyProcess,
+    pty: Stream,
+    /// Unique per session so command output can't fake the end of a command.
+    marker: String,
+    /// Private scratch directory, removed when the session is dropped.
+    dir: tempfile::TempDir,
+    /// `env_set` as last applied to the shell, to pick up changes made with the REPL `env` command.
+    env_set: BTreeMap<String, String>,
+    cwd: PathBuf,
+    env: BTreeMap<String, String>,
+}
+
+pub struct ShellRun {
+    pub output: String,
+    pub exit_code: Option<i32>,
+    /// Working directory and environment changes, for the model.
+    pub notes: Vec<String>,
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+/// Whether AI commands run in the persistent shell. Sandboxing and resource limits need a fresh
+/// process per command, so they fall back to one `bash -c` per command.
+pub fn is_active(config: &Config) -> bool {
+    config.persistent_shell
+        && config.shell_type.eq_ignore_ascii_case("bash")
+        && !sandbox::is_enabled(config)
+        && !sandbox::jail_enabled(config)
+        && !limits::has_rlimits(&config.limits)
+        && !limits::has_cgroup_limits(&config.limits)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Reads the NUL-separated `env -0` dump the shell writes after every command.
+fn read_env(path: &Path) -> BTreeMap<String, String> {
+    fs::read(path)
+        .map(|data| {
+            data.split(|b| *b == 0)
+                .filter_map(|entry| {
+                    let entry = String::from_utf8_lossy(entry);
+                    let (name, value) = entry.split_once('=')?;
+                    Some((name.to_string(), value.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Parses `\x1e<marker> <status> <cwd>\x1e` at the start of `text`.
+/// Returns `None` while it is incomplete.
+fn parse_sentinel(text: &str, marker: &str) -> Option<(i32, PathBuf)> {
+    let rest = text.strip_prefix('\u{1e}')?.strip_prefix(marker)?.strip_prefix(' ')?;
+    let end = rest.find('\u{1e}')?;
+    let (status, cwd) = rest[..end].split_once(' ')?;
+    Some((status.parse().unwrap_or(-1), PathBuf::from(cwd)))
+}
+
+/// Moves everything in `pending` that is command output to `output`, stopping at a (possibly
+/// partial) sentinel. Returns the parsed sentinel once it is complete.
+fn drain_output(pending: &mut String, marker: &str, mut output: impl FnMut(&str)) -> Option<(i32, PathBuf)> {
+    let prefix = format!("\u{1e}{} ", marker);
+    loop {
+        let Some(pos) = pending.find('\u{1e}') else {
+            output(pending);
+            pending.clear();
+            return None;
+        };
+        output(&pending[..pos]);
+        pending.drain(..pos);
+        let is_prefix = if pending.len() < prefix.len() { prefix.starts_with(pending.as_str()) } else { pending.starts_with(&prefix) };
+        if !is_prefix {
+            // A stray record separator in the output
+            output("\u{1e}");
+            pending.drain(..1);
+            continue;
+        }
+        return parse_sentinel(pending, marker);
+    }
+}
+
+fn start(config: &Config) -> Result<Session, Box<dyn std::error::Error>> {
+    // A random name created with mode 0700, so other users can't plant or read the files in it
+    let dir = tempfile::Builder::new().prefix("aiosc-shell-").permissions(fs::Permissions::from_mode(0o700)).tempdir()?;
+    let cwd = std::env::current_dir()?;
+
+    let mut cmd = Command::new("bash");
+    cmd.args(["--noprofile", "--norc", "--noediting", "+o", "history"]).current_dir(&cwd);
+    executor::apply_ai_env(config, &mut cmd);
+    let mut process = executor::spawn(cmd)?;
+    let (cols, rows) = executor::terminal_size();
+    process.set_window_size(cols, rows)?;
+    let mut pty = process.get_pty_stream()?;
+    executor::set_pty_echo(&pty, true)?;
+
+    let marker = format!(
+        "AIOSC{}{}",
+        std::process::id(),
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos()
+    );
+    let env_path = dir.path().join("env");
+    // Every prompt reports the exit status and working directory and dumps the environment.
+    // None of it is exported, so nested shells started by commands are unaffected.
+    let setup = format!(
+        "PS1=; PS2=; PROMPT_COMMAND='__aiosc_status=$?; env -0 >| {} 2>/dev/null; printf \"\\036{} %s %s\\036\" \"$__aiosc_status\" \"$PWD\"'; export -n PS1 PS2 PROMPT_COMMAND\n",
+        shell_quote(&env_path.display().to_string()).replace('\'', r"'\''"),
+        marker
+    );
+    pty.write_all(setup.as_bytes())?;
+    pty.flush()?;
+
+    // Discard the startup prompt until the first sentinel shows up
+    let pty_borrowed_fd = unsafe { BorrowedFd::borrow_raw(pty.as_raw_fd()) };
+    let started = Instant::now();
+    let mut pending = String::new();
+    let mut buffer = [0; 4096];
+    loop {
+        if started.elapsed() > Duration::from_secs(5) {
+            return Err(Box::new(io::Error::new(io::ErrorKind::TimedOut, "the persistent shell did not start")));
+        }
+        let mut poll_fds = [PollFd::new(pty_borrowed_fd, PollFlags::POLLIN)];
+        if poll(&mut poll_fds, 100u16)? == 0 {
+            continue;
+        }
+        let n = pty.read(&mut buffer)?;
+        if n == 0 {
+            return Err(Box::new(io::Error::new(io::ErrorKind::UnexpectedEof, "the persistent shell exited during startup")));
+        }
+        pending.push_str(&String::from_utf8_lossy(&buffer[..n]));
+        if let Some(pos) = pending.find(&format!("\u{1e}{} ", marker)) {
+            if parse_sentinel(&pending[pos..], &marker).is_some() {
+                break;
+            }
+        }
+    }
+
+    Ok(Session { process, pty, marker, env: read_env(&env_path), dir, env_set: config.env_set.clone(), cwd })
+}
+
+/// Describes variables that were set, changed or unset by a command.
+fn env_changes(before: &BTreeMap<String, String>, after: &BTreeMap<String, String>) -> Option<String> {
+    let relevant = |name: &&String| !IGNORED_VARIABLES.contains(&name.as_str());
+    let set: Vec<&String> = after.keys().filter(relevant).filter(|k| !before.contains_key(*k)).collect();
+    let changed: Vec<&String> = after.iter().filter(|(k, v)| before.get(*k).is_some_and(|old| old != *v)).map(|(k, _)| k).filter(relevant).collect();
+    let unset: Vec<&String> = before.keys().filter(relevant).filter(|k| !after.contains_key(*k)).collect();
+
+    let mut parts = Vec::new();
+    for (label, names) in [("set", set), ("changed", changed), ("unset", unset)] {
+        if !names.is_empty() {
+            parts.push(format!("{} {}", label, names.iter().map(|n| n.as_str()).collect::<Vec<_>>().join(", ")));
+        }
+    }
+    (!parts.is_empty()).then(|| format!("Shell environment: {}.", parts.join("; ")))
+}
+
+/// Runs a command in the session's shell, starting the shell first if needed.
+pub fn run(config: &Config, command: &str, show_output: bool) -> Result<ShellRun, Box<dyn std::error::Error>> {
+    let mut guard = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.is_none() {
+        *guard = Some(start(config)?);
+    }
+    let session = guard.as_mut().expect("session");
+
+    // Follow changes made outside the shell: `env` in the REPL and aiosc's own working directory
+    let mut script = String::new();
+    for (name, value) in &config.env_set {
+        if session.env_set.get(name) != Some(value) {
+            script.push_str(&format!("export {}={}\n", name, shell_quote(value)));
+        }
+    }
+    for name in session.env_set.keys().filter(|name| !config.env_set.contains_key(*name)) {
+        script.push_str(&format!("unset {}\n", name));
+    }
+    session.env_set = config.env_set.clone();
+    if let Ok(cwd) = std::env::current_dir() {
+        if cwd != session.cwd {
+            script.push_str(&format!("cd {}\n", shell_quote(&cwd.display().to_string())));
+            session.cwd = cwd;
+        }
+    }
+    script.push_str(command);
+    script.push('\n');
+    let script_path = session.dir.path().join("command.sh");
+    fs::write(&script_path, script)?;
+
+    let (cols, rows) = executor::terminal_size();
+    session.process.set_window_size(cols, rows)?;
+    let line = format!(". {}", shell_quote(&script_path.display().to_string()));
+    session.pty.write_all(format!("{}\n", line).as_bytes())?;
+    session.pty.flush()?;
+
+    let marker = session.marker.clone();
+    let mut output = String::new();
+    let mut pending = String::new();
+    // The terminal echoes the line above before the command runs
+    let mut echo = format!("{}\r\n", line);
+    let mut finished = None;
+    executor::relay(&mut session.pty, |chunk| {
+        pending.push_str(chunk);
+        if !echo.is_empty() {
+            if pending.starts_with(&echo) {
+                pending.drain(..echo.len());
+                echo.clear();
+            } else if echo.starts_with(pending.as_str()) {
+                return true;
+            } else {
+                echo.clear();
+            }
+        }
+        finished = drain_output(&mut pending, &marker, |text| {
+            if show_output && !text.is_empty() {
+                print!("{}", text);
+                let _ = io::stdout().flush();
+            }
+            output.push_str(text);
+        });
+        finished.is_none()
+    })?;
+
+    if interrupt::aborted() {
+        // Whatever the command left running can't be told apart from the shell's own state
+        executor::kill_foreground(&session.pty);
+        if let Some(mut session) = guard.take() {
+            let _ = session.process.exit(true);
+        }
+        return Ok(ShellRun {
+            output,
+            exit_code: None,
+            notes: vec!["The shell session was ended by the abort. The next command starts a new one, so variables and functions are reset.".to_string()],
+        });
+    }
+
+    let Some((status, cwd)) = finished else {
+        // The shell itself exited, e.g. after `exit` or `set -e`
+        let exit_code = match guard.take().map(|session| session.process.wait()) {
+            Some(Ok(ptyprocess::WaitStatus::Exited(_, code))) => Some(code),
+            _ => None,
+        };
+        return Ok(ShellRun {
+            output,
+            exit_code,
+            notes: vec!["The shell session exited. The next command starts a new one, so variables and functions are reset.".to_string()],
+        });
+    };
+
+    let mut notes = Vec::new();
+    if cwd != session.cwd {
+        // Keep aiosc in the same directory, so relative paths mean the same to both
+        if std::env::set_current_dir(&cwd).is_ok() {
+            session.cwd = cwd;
+            notes.push(format!("Working directory is now {}.", session.cwd.display()));
+        }
+    }
+    let env = read_env(&session.dir.path().join("env"));
+    if let Some(changes) = env_changes(&session.env, &env) {
+        notes.push(changes);
+    }
+    session.env = env;
+    Ok(ShellRun { output, exit_code: Some(status), notes })
+}
+
+/// Ends the session's shell; the next command starts a fresh one.
+pub fn close() {
+    let session = SESSION.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(mut session) = session {
+        let _ = session.process.exit(true);
+    }
+}
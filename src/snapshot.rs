@@ -0,0 +1,223 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::{config::Config, safety};
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    command: String,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    existed: bool,
+    /// Restored by copying the saved files back without removing anything else, for the whole
+    /// working directory saved when nothing could be predicted.
+    #[serde(default)]
+    merge: bool,
+}
+
+fn store_dir() -> PathBuf {
+    let mut dir = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    dir.push("aiosc");
+    dir.push("snapshots");
+    dir
+}
+
+/// Snapshots live for the duration of one session, keyed by process id.
+fn session_dir() -> PathBuf {
+    store_dir().join(std::process::id().to_string())
+}
+
+/// Removes the snapshots of sessions whose process is gone, e.g. after a crash.
+fn remove_stale_sessions() {
+    let Ok(entries) = fs::read_dir(store_dir()) else { return };
+    for entry in entries.filter_map(Result::ok) {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) else { continue };
+        let alive = !matches!(nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None), Err(nix::errno::Errno::ESRCH));
+        if !alive {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+fn steps() -> Vec<(usize, PathBuf)> {
+    let mut steps: Vec<(usize, PathBuf)> = fs::read_dir(session_dir())
+        .map(|entries| entries
+            .filter_map(Result::ok)
+            .filter_map(|e| e.file_name().to_str()?.parse().ok().map(|n| (n, e.path())))
+            .collect())
+        .unwrap_or_default();
+    steps.sort_by_key(|(n, _)| *n);
+    steps
+}
+
+/// Total size of a path, or `None` as soon as it exceeds `limit`.
+fn size_within(path: &Path, limit: u64) -> Option<u64> {
+    let meta = path.symlink_metadata().ok()?;
+    if !meta.is_dir() {
+        return (meta.len() <= limit).then_some(meta.len());
+    }
+    let mut total = 0;
+    for entry in fs::read_dir(path).ok()?.filter_map(Result::ok) {
+        total += size_within(&entry.path(), limit.checked_sub(total)?)?;
+    }
+    Some(total)
+}
+
+fn copy_recursive(src: &Path, dst: &Path) -> io::Result<()> {
+    let meta = src.symlink_metadata()?;
+    if meta.file_type().is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(src)?, dst)
+    } else if meta.is_dir() {
+        fs::create_dir_all(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        fs::set_permissions(dst, meta.permissions())
+    } else if meta.is_file() {
+        fs::copy(src, dst).map(|_| ())
+    } else {
+        // Sockets, fifos and devices are not snapshotted
+        Ok(())
+    }
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Empties a directory without removing it, so it stays valid as a working directory.
+fn clear_dir(path: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(path)? {
+        remove_path(&entry?.path())?;
+    }
+    Ok(())
+}
+
+/// Copies the saved `stored` back into `path` file by file, leaving everything else in it alone.
+fn merge_back(stored: &Path, path: &Path) -> io::Result<()> {
+    for child in fs::read_dir(stored)? {
+        let child = child?;
+        let (source, target) = (child.path(), path.join(child.file_name()));
+        if source.is_dir() && !source.is_symlink() && target.is_dir() && !target.is_symlink() {
+            merge_back(&source, &target)?;
+        } else {
+            remove_path(&target).and_then(|_| copy_recursive(&source, &target))?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves the files a command is predicted to modify; the ones that don't exist yet are removed
+/// again on undo. Falls back to the whole working directory when nothing can be predicted.
+/// Returns the step number.
+pub fn take(config: &Config, command: &str) -> Result<usize, String> {
+    let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+    let mut paths = safety::predicted_paths(command, &cwd);
+    let merge = paths.is_empty();
+    if merge {
+        paths.push(cwd);
+    }
+
+    let store = store_dir();
+    let mut budget = config.snapshot_max_size;
+    for path in paths.iter().filter(|p| p.symlink_metadata().is_ok()) {
+        if store.starts_with(path) {
+            return Err(format!("'{}' contains the snapshot store", path.display()));
+        }
+        match size_within(path, budget) {
+            Some(size) => budget -= size,
+            None => return Err(format!("'{}' is larger than snapshot_max_size", path.display())),
+        }
+    }
+
+    if !session_dir().exists() {
+        remove_stale_sessions();
+    }
+    let step = steps().last().map(|(n, _)| n + 1).unwrap_or(1);
+    let step_dir = session_dir().join(step.to_string());
+    fs::create_dir_all(step_dir.join("data")).map_err(|e| e.to_string())?;
+
+    let mut entries = Vec::new();
+    for (i, path) in paths.into_iter().enumerate() {
+        let existed = path.symlink_metadata().is_ok();
+        if existed {
+            if let Err(e) = copy_recursive(&path, &step_dir.join("data").join(i.to_string())) {
+                let _ = fs::remove_dir_all(&step_dir);
+                return Err(format!("failed to copy '{}': {}", path.display(), e));
+            }
+        }
+        entries.push(Entry { path, existed, merge });
+    }
+
+    let manifest = Manifest { command: command.to_string(), entries };
+    let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+    fs::write(step_dir.join("manifest.json"), json).map_err(|e| e.to_string())?;
+    Ok(step)
+}
+
+fn restore_step(step_dir: &Path) -> Result<String, String> {
+    let json = fs::read_to_string(step_dir.join("manifest.json")).map_err(|e| e.to_string())?;
+    let manifest: Manifest = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+
+    for (i, entry) in manifest.entries.iter().enumerate().rev() {
+        let stored = step_dir.join("data").join(i.to_string());
+        let result = if !entry.existed {
+            remove_path(&entry.path)
+        } else if entry.merge && stored.is_dir() && entry.path.is_dir() {
+            merge_back(&stored, &entry.path)
+        } else if stored.is_dir() && !stored.is_symlink() && entry.path.is_dir() && !entry.path.is_symlink() {
+            clear_dir(&entry.path).and_then(|_| {
+                for child in fs::read_dir(&stored)? {
+                    let child = child?;
+                    copy_recursive(&child.path(), &entry.path.join(child.file_name()))?;
+                }
+                Ok(())
+            })
+        } else {
+            remove_path(&entry.path).and_then(|_| copy_recursive(&stored, &entry.path))
+        };
+        result.map_err(|e| format!("failed to restore '{}': {}", entry.path.display(), e))?;
+    }
+
+    fs::remove_dir_all(step_dir).map_err(|e| e.to_string())?;
+    Ok(manifest.command)
+}
+
+/// Restores the last `count` snapshots, newest first. Returns the commands that were undone.
+pub fn undo(count: usize) -> Result<Vec<String>, String> {
+    let mut undone = Vec::new();
+    for (_, step_dir) in steps().into_iter().rev().take(count) {
+        undone.push(restore_step(&step_dir)?);
+    }
+    Ok(undone)
+}
+
+/// Commands that can still be undone, oldest first.
+pub fn list() -> Vec<(usize, String)> {
+    steps()
+        .into_iter()
+        .filter_map(|(n, dir)| {
+            let json = fs::read_to_string(dir.join("manifest.json")).ok()?;
+            let manifest: Manifest = serde_json::from_str(&json).ok()?;
+            Some((n, manifest.command))
+        })
+        .collect()
+}
+
+pub fn cleanup() {
+    let dir = session_dir();
+    if dir.exists() {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
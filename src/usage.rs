@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use crate::{audit, config::Config};
+
+/// Token counts and cost of a number of requests.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+pub struct Totals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// In the currency of `prices`, for the requests whose model has a price.
+    pub cost: f64,
+    /// Requests whose model has no price, so `cost` is a lower bound.
+    #[serde(default)]
+    pub unpriced: u64,
+}
+
+impl Totals {
+    fn add(&mut self, other: &Totals) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cost += other.cost;
+        self.unpriced += other.unpriced;
+    }
+
+    /// E.g. `3 requests, 1200 prompt + 150 completion tokens, $0.0004`.
+    pub fn summary(&self) -> String {
+        let mut text = format!(
+            "{} request{}, {} prompt + {} completion tokens",
+            self.requests,
+            if self.requests == 1 { "" } else { "s" },
+            self.prompt_tokens,
+            self.completion_tokens
+        );
+        if self.unpriced < self.requests {
+            text.push_str(&format!(", ${:.4}", self.cost));
+            if self.unpriced > 0 {
+                text.push_str(&format!(" (+{} without a price)", self.unpriced));
+            }
+        }
+        text
+    }
+}
+
+static SESSION: Mutex<Totals> = Mutex::new(Totals { requests: 0, prompt_tokens: 0, completion_tokens: 0, cost: 0.0, unpriced: 0 });
+static LAST: Mutex<Option<Totals>> = Mutex::new(None);
+
+pub fn get_usage_path(config: &Config) -> PathBuf {
+    if !config.usage_path.is_empty() {
+        return PathBuf::from(&config.usage_path);
+    }
+    let mut data_dir = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    data_dir.push("aiosc");
+    data_dir.push("usage.json");
+    data_dir
+}
+
+/// The current month in UTC, e.g. `2025-03`, the key of the monthly totals.
+fn current_month() -> String {
+    audit::utc_timestamp(SystemTime::now())[..7].to_string()
+}
+
+fn read_months(config: &Config) -> BTreeMap<String, Totals> {
+    fs::read_to_string(get_usage_path(config))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// Counts one request from the `usage` block of a chat completions response. Requests served
+/// from a cassette only count for the session, since they cost nothing.
+pub fn record(config: &Config, response: &serde_json::Value) {
+    let usage = &response["usage"];
+    let prompt_tokens = usage["prompt_tokens"].as_u64().unwrap_or(0);
+    let completion_tokens = usage["completion_tokens"].as_u64().unwrap_or(0);
+    let price = config.prices.get(&config.model);
+    let call = Totals {
+        requests: 1,
+        prompt_tokens,
+        completion_tokens,
+        cost: price.map_or(0.0, |p| (prompt_tokens as f64 * p.prompt + completion_tokens as f64 * p.completion) / 1_000_000.0),
+        unpriced: if price.is_some() { 0 } else { 1 },
+    };
+
+    SESSION.lock().unwrap_or_else(|e| e.into_inner()).add(&call);
+    *LAST.lock().unwrap_or_else(|e| e.into_inner()) = Some(call);
+    if config.debug {
+        println!("{}", format!("[Usage] {}", call.summary()).truecolor(128, 128, 128));
+    }
+    if !config.replay.is_empty() {
+        return;
+    }
+
+    let mut months = read_months(config);
+    months.entry(current_month()).or_default().add(&call);
+    let path = get_usage_path(config);
+    let written = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| {
+        fs::write(&path, serde_json::to_string_pretty(&months).unwrap_or_default())
+    });
+    if let Err(e) = written {
+        println!("{}", format!("Failed to write usage file '{}': {}", path.display(), e).red());
+    }
+}
+
+/// Everything counted since aiosc started.
+pub fn session() -> Totals {
+    *SESSION.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The most recent request.
+pub fn last() -> Option<Totals> {
+    *LAST.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Everything counted this month, across sessions.
+pub fn month(config: &Config) -> Totals {
+    read_months(config).remove(&current_month()).unwrap_or_default()
+}
+
+/// Why no more requests may be sent, once this month's cost reached `monthly_budget`.
+pub fn budget_exceeded(config: &Config) -> Option<String> {
+    if config.monthly_budget <= 0.0 {
+        return None;
+    }
+    let spent = month(config).cost;
+    (spent >= config.monthly_budget).then(|| {
+        format!("Monthly budget of ${:.2} reached (${:.4} spent in {}).", config.monthly_budget, spent, current_month())
+    })
+}
+
+/// The REPL `usage` command.
+pub fn print_report(config: &Config) {
+    let gray = |text: String| println!("{}", text.truecolor(128, 128, 128));
+    if let Some(last) = last() {
+        gray(format!("Last request: {}", last.summary()));
+    }
+    gray(format!("This session: {}", session().summary()));
+    gray(format!("This month:   {}", month(config).summary()));
+    if config.monthly_budget > 0.0 {
+        gray(format!("Monthly budget: ${:.2}", config.monthly_budget));
+    }
+    if !config.prices.contains_key(&config.model) {
+        gray(format!("No price is configured for '{}'; see `prices` in the config.", config.model));
+    }
+}
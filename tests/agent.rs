@@ -0,0 +1,115 @@
+mod support;
+
+use std::collections::BTreeMap;
+use serde_json::json;
+use aiosc::usage;
+use support::{run_agent, test_config};
+
+fn replies(replies: &[&str]) -> Vec<String> {
+    replies.iter().map(|r| r.to_string()).collect()
+}
+
+#[test]
+fn states_follow_the_loop() {
+    let config = test_config(&json!({ "require_confirmation": true }));
+    let outcome = run_agent(&config, "uptime?", &replies(&["<cmd>uptime</cmd>", "Up for a day."]), BTreeMap::new(), &["run".to_string()]);
+    assert_eq!(
+        outcome.record.states,
+        ["awaiting-model", "awaiting-approval", "executing", "awaiting-model", "done"]
+    );
+}
+
+#[test]
+fn trimming_keeps_the_system_prompt() {
+    let config = test_config(&json!({ "context_window_size": 2 }));
+    let script = replies(&["<cmd>echo 1</cmd>", "<cmd>echo 2</cmd>", "<cmd>echo 3</cmd>", "Done."]);
+    let outcome = run_agent(&config, "count", &script, BTreeMap::new(), &[]);
+    assert_eq!(outcome.record.requests.len(), 4);
+    for request in &outcome.record.requests {
+        assert_eq!(request[0].role, "system");
+        assert!(request.len() <= 3, "request with {} messages", request.len());
+    }
+    // The latest command result survives trimming
+    let last = outcome.record.requests.last().expect("request");
+    assert_eq!(last.last().map(|m| m.content.as_str()), Some("Success"));
+}
+
+#[test]
+fn path_jail_confirm_asks_even_without_require_confirmation() {
+    let config = test_config(&json!({ "path_jail": "confirm", "allowed_roots": ["/nonexistent/aiosc-jail"] }));
+    let script = replies(&["<cmd>touch /tmp/aiosc-outside</cmd>", "Skipped."]);
+    let outcome = run_agent(&config, "touch a file", &script, BTreeMap::new(), &["skip".to_string()]);
+    assert_eq!(outcome.record.confirmations, ["touch /tmp/aiosc-outside"]);
+    assert!(outcome.record.commands.is_empty());
+}
+
+#[test]
+fn unterminated_command_tag_ends_the_task() {
+    let config = test_config(&json!({}));
+    let outcome = run_agent(&config, "list", &replies(&["<cmd>ls"]), BTreeMap::new(), &[]);
+    assert!(outcome.record.commands.is_empty());
+    assert_eq!(outcome.state.as_ref().map(|s| s.name()), Ok("done"));
+}
+
+#[test]
+fn usage_is_priced_and_kept_per_month() {
+    let path = std::env::temp_dir().join(format!("aiosc-usage-test-{}.json", std::process::id()));
+    let config = test_config(&json!({
+        "model": "priced",
+        "usage_path": path.display().to_string(),
+        "prices": { "priced": { "prompt": 2.0, "completion": 10.0 } }
+    }));
+    let response = json!({ "usage": { "prompt_tokens": 1_000_000, "completion_tokens": 50_000 } });
+    usage::record(&config, &response);
+    usage::record(&config, &response);
+    let month = usage::month(&config);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!((month.requests, month.prompt_tokens, month.completion_tokens, month.unpriced), (2, 2_000_000, 100_000, 0));
+    assert!((month.cost - 5.0).abs() < 1e-9, "cost {}", month.cost);
+}
+
+#[test]
+fn exceeded_budget_stops_before_asking_the_model() {
+    let path = std::env::temp_dir().join(format!("aiosc-budget-test-{}.json", std::process::id()));
+    let config = test_config(&json!({
+        "model": "priced",
+        "usage_path": path.display().to_string(),
+        "monthly_budget": 1.0,
+        "prices": { "priced": { "prompt": 2.0, "completion": 0.0 } }
+    }));
+    usage::record(&config, &json!({ "usage": { "prompt_tokens": 600_000, "completion_tokens": 0 } }));
+    let outcome = run_agent(&config, "uptime?", &replies(&["<cmd>uptime</cmd>"]), BTreeMap::new(), &[]);
+    let _ = std::fs::remove_file(&path);
+    assert!(outcome.record.requests.is_empty());
+    assert_eq!(outcome.state.as_ref().map(|s| s.name()), Ok("aborted"));
+}
+
+#[test]
+fn commands_that_do_not_run_are_audited_and_rotation_keeps_history() {
+    let path = std::env::temp_dir().join(format!("aiosc-audit-test-{}.jsonl", std::process::id()));
+    let config = test_config(&json!({
+        "read_only": true,
+        "require_confirmation": true,
+        "audit_log": true,
+        "audit_log_path": path.display().to_string(),
+        "audit_log_max_size": 1,
+        "audit_log_max_files": 0
+    }));
+    let script = replies(&["<cmd>rm notes.txt</cmd>", "<cmd>uptime</cmd>", "Fine."]);
+    run_agent(&config, "tidy up", &script, BTreeMap::new(), &["skip".to_string()]);
+
+    let rotated = std::path::PathBuf::from(format!("{}.1", path.display()));
+    let lines: Vec<serde_json::Value> = [&rotated, &path]
+        .iter()
+        .flat_map(|file| std::fs::read_to_string(file).unwrap_or_default().lines().map(String::from).collect::<Vec<_>>())
+        .map(|line| serde_json::from_str(&line).expect("audit entry"))
+        .collect();
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&rotated);
+
+    let decisions: Vec<(&str, &str, bool)> = lines
+        .iter()
+        .map(|e| (e["command"].as_str().unwrap_or(""), e["approval"].as_str().unwrap_or(""), e["executed"].as_bool().unwrap_or(true)))
+        .collect();
+    assert_eq!(decisions, [("rm notes.txt", "read-only", false), ("uptime", "skipped", false)]);
+}
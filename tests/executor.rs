@@ -0,0 +1,34 @@
+use aiosc::config::Config;
+use aiosc::executor::execute_command;
+
+#[test]
+fn a_leading_cd_moves_the_working_directory_only_after_the_command_succeeded() {
+    let dir = tempfile::tempdir().unwrap();
+    let (start, sub) = (dir.path().canonicalize().unwrap(), dir.path().canonicalize().unwrap().join("sub"));
+    std::fs::create_dir(&sub).unwrap();
+    std::env::set_current_dir(&start).unwrap();
+    let config = Config::default();
+    let run = |command: &str| execute_command(&config, command, true, false, true).unwrap();
+
+    let failed = run("cd sub && false");
+    assert_eq!(failed.exit_code, Some(1));
+    assert_eq!(std::env::current_dir().unwrap(), start);
+
+    let missing = run("cd missing && true");
+    assert!(missing.result.starts_with("Error") && missing.result.contains("cd: missing"), "{}", missing.result);
+    assert_eq!(std::env::current_dir().unwrap(), start);
+
+    // After a failed `cd` the shell runs the rest as written: `;` goes on, `&&` doesn't
+    let sequenced = run("cd missing; pwd");
+    assert_eq!(sequenced.exit_code, Some(0));
+    assert!(sequenced.output.contains("missing") && sequenced.output.contains(&start.display().to_string()), "{}", sequenced.output);
+    let chained = run("cd missing && echo skipped; echo ran");
+    assert!(!chained.output.contains("skipped") && chained.output.contains("ran"), "{}", chained.output);
+    assert_eq!(std::env::current_dir().unwrap(), start);
+
+    let moved = run("cd sub && pwd");
+    assert_eq!(moved.exit_code, Some(0));
+    assert!(moved.output.contains(&sub.display().to_string()), "{}", moved.output);
+    assert!(moved.result.ends_with(&format!("Working directory is now {}.", sub.display())), "{}", moved.result);
+    assert_eq!(std::env::current_dir().unwrap(), sub);
+}
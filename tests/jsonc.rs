@@ -0,0 +1,45 @@
+use serde_json::json;
+use aiosc::jsonc::{remove, set};
+
+const CONFIG: &str = r#"{
+  // Where the model lives
+  "api_addr": "https://openrouter.ai/api/v1",
+  "model": "qwen", // the default
+  "limits": {
+    "cpu_seconds": 60
+  }
+}
+"#;
+
+#[test]
+fn set_keeps_comments() {
+    let text = set(CONFIG, &["model"], &json!("deepseek")).expect("set");
+    assert!(text.contains(r#""model": "deepseek", // the default"#), "{}", text);
+    assert!(text.contains("// Where the model lives"));
+
+    let text = set(&text, &["limits", "processes"], &json!(64)).expect("set");
+    assert!(text.contains("    \"cpu_seconds\": 60,\n    \"processes\": 64\n  }"), "{}", text);
+
+    let text = set(&text, &["model_params", "*", "temperature"], &json!(0.2)).expect("set");
+    let parsed: serde_json::Value = serde_json::from_reader(json_comments::StripComments::new(text.as_bytes())).expect("valid JSON");
+    assert_eq!(parsed["model_params"]["*"]["temperature"], json!(0.2));
+    assert_eq!(parsed["limits"], json!({ "cpu_seconds": 60, "processes": 64 }));
+
+    assert_eq!(set("", &["model"], &json!("x")).expect("set"), "{\n  \"model\": \"x\"\n}\n");
+}
+
+#[test]
+fn remove_takes_the_member_and_its_comma() {
+    let text = remove(CONFIG, &["model"]).expect("remove").expect("present");
+    assert!(!text.contains("\"model\"") && !text.contains("the default"), "{}", text);
+    assert!(text.contains("  \"api_addr\": \"https://openrouter.ai/api/v1\",\n  \"limits\""), "{}", text);
+
+    let text = remove(&text, &["limits"]).expect("remove").expect("present");
+    let parsed: serde_json::Value = serde_json::from_reader(json_comments::StripComments::new(text.as_bytes())).expect("valid JSON");
+    assert_eq!(parsed, json!({ "api_addr": "https://openrouter.ai/api/v1" }));
+
+    let text = remove(&text, &["api_addr"]).expect("remove").expect("present");
+    let parsed: serde_json::Value = serde_json::from_reader(json_comments::StripComments::new(text.as_bytes())).expect("valid JSON");
+    assert_eq!(parsed, json!({}));
+    assert!(remove(&text, &["missing"]).expect("remove").is_none());
+}
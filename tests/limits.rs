@@ -0,0 +1,13 @@
+use aiosc::config::Limits;
+use aiosc::limits::{describe_limit_hit, CgroupEvents};
+
+#[test]
+fn sigkill_is_only_blamed_on_the_limit_that_was_reached() {
+    let limits = Limits { cpu_seconds: 10, cgroup_memory_max: "1G".to_string(), ..Limits::default() };
+    let hit = |events: &CgroupEvents| describe_limit_hit(&limits, Some("SIGKILL"), "", events).unwrap_or_default();
+
+    assert!(hit(&CgroupEvents { cpu_seconds: 15.0, ..CgroupEvents::default() }).contains("limit=cpu_seconds"));
+    assert!(hit(&CgroupEvents { oom_kills: 1, cpu_seconds: 15.0, ..CgroupEvents::default() }).contains("limit=cgroup_memory_max"));
+    // Killed by someone else after a second of CPU time
+    assert_eq!(hit(&CgroupEvents { cpu_seconds: 1.0, ..CgroupEvents::default() }), "");
+}
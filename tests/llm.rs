@@ -0,0 +1,31 @@
+mod support;
+
+use serde_json::json;
+use aiosc::llm::request_body;
+use aiosc::message::Message;
+use support::test_config;
+
+#[test]
+fn model_params_merge_from_general_to_specific() {
+    let config = test_config(&json!({
+        "model": "openai/gpt-4o-mini",
+        "model_params": {
+            "*": { "temperature": 0.2, "max_tokens": 512, "model": "ignored" },
+            "openai/*": { "temperature": 0.7, "seed": 7 },
+            "openai/gpt-4o-mini": { "provider": { "order": ["Azure"] } },
+            "anthropic/*": { "top_p": 0.9 }
+        }
+    }));
+    let conversation = [Message { role: "user".to_string(), content: "hi".to_string() }];
+    assert_eq!(
+        request_body(&config, &conversation),
+        json!({
+            "model": "openai/gpt-4o-mini",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "temperature": 0.7,
+            "max_tokens": 512,
+            "seed": 7,
+            "provider": { "order": ["Azure"] }
+        })
+    );
+}
@@ -0,0 +1,24 @@
+mod support;
+
+use std::path::Path;
+
+/// Runs every scenario in tests/scenarios and reports all mismatches at once.
+#[test]
+fn scenarios() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .expect("tests/scenarios")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no scenarios in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        for failure in support::run_scenario(path) {
+            failures.push(format!("{}: {}", path.file_name().unwrap_or_default().to_string_lossy(), failure));
+        }
+    }
+    assert!(failures.is_empty(), "{} scenario check(s) failed:\n{}", failures.len(), failures.join("\n"));
+}
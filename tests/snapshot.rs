@@ -0,0 +1,38 @@
+mod support;
+
+use std::fs;
+use serde_json::json;
+use aiosc::snapshot;
+use support::test_config;
+
+#[test]
+fn undo_only_touches_what_the_command_was_predicted_to_change() {
+    let root = tempfile::tempdir().unwrap();
+    let (data, work) = (root.path().join("data"), root.path().join("work"));
+    fs::create_dir_all(&work).unwrap();
+    fs::write(work.join("keep.txt"), "original").unwrap();
+    std::env::set_var("XDG_DATA_HOME", &data);
+    std::env::set_current_dir(&work).unwrap();
+    // Left behind by a session that crashed
+    let stale = data.join("aiosc/snapshots/4194305");
+    fs::create_dir_all(stale.join("1")).unwrap();
+    let config = test_config(&json!({}));
+
+    // Only creates a file: undo removes it and leaves unrelated changes alone
+    snapshot::take(&config, "touch new.txt").unwrap();
+    assert!(!stale.exists());
+    fs::write(work.join("new.txt"), "").unwrap();
+    fs::write(work.join("unrelated.txt"), "meanwhile").unwrap();
+    snapshot::undo(1).unwrap();
+    assert!(!work.join("new.txt").exists());
+    assert!(work.join("unrelated.txt").exists());
+
+    // Nothing predictable: the saved files come back, nothing else is removed
+    snapshot::take(&config, "make").unwrap();
+    fs::write(work.join("keep.txt"), "changed").unwrap();
+    fs::write(work.join("built.o"), "").unwrap();
+    snapshot::undo(1).unwrap();
+    assert_eq!(fs::read_to_string(work.join("keep.txt")).unwrap(), "original");
+    assert!(work.join("built.o").exists() && work.join("unrelated.txt").exists());
+    snapshot::cleanup();
+}
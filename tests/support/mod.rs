@@ -0,0 +1,271 @@
+//! Offline test doubles for the agent: a scripted model, a fake command runner and scripted
+//! confirmations, plus the JSON scenario format built on them.
+
+// Each test crate uses a different part of this
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use aiosc::agent::{Agent, Confirmation, Hooks, State, Step};
+use aiosc::config::Config;
+use aiosc::executor::{command_result, CommandRunner, Execution};
+use aiosc::llm::LlmClient;
+use aiosc::message::Message;
+
+/// Everything the doubles saw during a run.
+#[derive(Default)]
+pub struct Record {
+    /// Conversations sent to the model, in order.
+    pub requests: Vec<Vec<Message>>,
+    /// Commands that reached the runner.
+    pub commands: Vec<String>,
+    /// Proposed commands the user was asked about.
+    pub confirmations: Vec<String>,
+    /// Results sent back to the model.
+    pub results: Vec<String>,
+    /// States the agent went through.
+    pub states: Vec<String>,
+}
+
+pub type Shared = Rc<RefCell<Record>>;
+
+/// Replies with the scripted responses in order, and fails once they run out.
+pub struct ScriptedLlm {
+    pub replies: Vec<String>,
+    pub record: Shared,
+}
+
+impl LlmClient for ScriptedLlm {
+    fn query(&mut self, _config: &Config, conversation: &[Message]) -> Result<String, Box<dyn std::error::Error>> {
+        let mut record = self.record.borrow_mut();
+        record.requests.push(conversation.to_vec());
+        let index = record.requests.len() - 1;
+        self.replies
+            .get(index)
+            .cloned()
+            .ok_or_else(|| format!("the script has no reply #{}", index + 1).into())
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct FakeResult {
+    #[serde(default)]
+    pub output: String,
+    #[serde(default)]
+    pub exit_code: i32,
+}
+
+/// Returns canned results by command; unknown commands succeed without output.
+pub struct FakeRunner {
+    pub results: BTreeMap<String, FakeResult>,
+    pub record: Shared,
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(&mut self, _config: &Config, command: &str, needs_full_context: bool, _silent: bool) -> Result<Execution, Box<dyn std::error::Error>> {
+        self.record.borrow_mut().commands.push(command.to_string());
+        let fake = self.results.get(command).cloned().unwrap_or_default();
+        Ok(Execution {
+            result: command_result(Some(fake.exit_code), None, &fake.output, needs_full_context, false),
+            output: fake.output,
+            exit_code: Some(fake.exit_code),
+            duration: Duration::ZERO,
+        })
+    }
+}
+
+/// Answers confirmations from a script: `run`, `skip`, `abort` or `edit:<command>`. Aborts once
+/// the script runs out.
+pub struct ScriptedHooks {
+    pub answers: Vec<String>,
+    pub record: Shared,
+}
+
+impl Hooks for ScriptedHooks {
+    fn on_state(&mut self, state: &State) {
+        self.record.borrow_mut().states.push(state.name().to_string());
+    }
+
+    fn on_output(&mut self, _step: &Step, output: &str) {
+        self.record.borrow_mut().results.push(output.to_string());
+    }
+
+    fn confirm(&mut self, _config: &Config, _conversation: &[Message], step: &Step) -> Result<Confirmation, Box<dyn std::error::Error>> {
+        let mut record = self.record.borrow_mut();
+        record.confirmations.push(step.proposed.clone());
+        let answer = self.answers.get(record.confirmations.len() - 1).map(String::as_str).unwrap_or("abort");
+        Ok(match answer {
+            "run" => Confirmation::Run(step.proposed.clone()),
+            "skip" => Confirmation::Skip,
+            "abort" => Confirmation::Abort,
+            edit => match edit.strip_prefix("edit:") {
+                Some(command) => Confirmation::Run(command.to_string()),
+                None => return Err(format!("unknown scripted answer '{}'", edit).into()),
+            },
+        })
+    }
+}
+
+/// A config that touches nothing outside the test: no audit log, snapshots or cooldown.
+/// `overrides` are merged on top, as in a config file.
+pub fn test_config(overrides: &Value) -> Config {
+    let mut base = json!({
+        "debug": false,
+        "api_addr": "http://127.0.0.1:9",
+        "api_key": "",
+        "model": "scripted",
+        "show_ai_commands_output": false,
+        "context_window_size": 32,
+        "shell_type": "bash",
+        "require_confirmation": false,
+        "cooldown": 0,
+        "snapshots": false,
+        "redact_secrets": true,
+    });
+    if let (Some(base), Some(overrides)) = (base.as_object_mut(), overrides.as_object()) {
+        for (key, value) in overrides {
+            base.insert(key.clone(), value.clone());
+        }
+    }
+    serde_json::from_value(base).expect("test config")
+}
+
+/// The outcome of a run.
+pub struct Outcome {
+    pub state: Result<State, String>,
+    pub conversation: Vec<Message>,
+    pub record: Record,
+}
+
+impl Outcome {
+    /// What aiosc's inline mode would exit with.
+    pub fn exit_code(&self) -> i32 {
+        if self.state.is_ok() { 0 } else { 1 }
+    }
+}
+
+/// Runs one task against the doubles.
+pub fn run_agent(config: &Config, prompt: &str, replies: &[String], results: BTreeMap<String, FakeResult>, answers: &[String]) -> Outcome {
+    let record = Shared::default();
+    let mut conversation = vec![
+        Message { role: "system".to_string(), content: "You are a test.".to_string() },
+        Message { role: "user".to_string(), content: prompt.to_string() },
+    ];
+    let state = {
+        let mut agent = Agent::new(config, &mut conversation, true);
+        agent.llm = Box::new(ScriptedLlm { replies: replies.to_vec(), record: record.clone() });
+        agent.runner = Box::new(FakeRunner { results, record: record.clone() });
+        agent.hooks = Box::new(ScriptedHooks { answers: answers.to_vec(), record: record.clone() });
+        agent.run(None).map_err(|e| e.to_string())
+    };
+    let record = Rc::try_unwrap(record).ok().expect("agent dropped").into_inner();
+    Outcome { state, conversation, record }
+}
+
+/// A scenario file. Everything under `expect` is optional; only what is given is checked. Other
+/// keys, such as a `description`, are ignored.
+#[derive(Deserialize)]
+pub struct Scenario {
+    /// Config file settings on top of `test_config`.
+    #[serde(default)]
+    pub config: Value,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub prompt: String,
+    /// Model responses, in order.
+    #[serde(default)]
+    pub replies: Vec<String>,
+    /// A cassette recorded with `--record`, relative to the scenario file. Its responses are
+    /// added after `replies`.
+    #[serde(default)]
+    pub cassette: Option<String>,
+    /// Fake results by command.
+    #[serde(default)]
+    pub results: BTreeMap<String, FakeResult>,
+    /// Answers to confirmation prompts, in order.
+    #[serde(default)]
+    pub confirmations: Vec<String>,
+    #[serde(default)]
+    pub expect: Expect,
+}
+
+#[derive(Deserialize, Default)]
+pub struct Expect {
+    /// Commands that were actually run.
+    pub commands: Option<Vec<String>>,
+    /// Commands the user was asked to confirm.
+    pub confirmations: Option<Vec<String>>,
+    /// Final state: `done` or `aborted`.
+    pub state: Option<String>,
+    /// 0 when the task ended normally, 1 on an error such as a failed LLM request.
+    pub exit_code: Option<i32>,
+    /// Substrings the results sent back to the model must contain, one per result in order.
+    pub results: Option<Vec<String>>,
+    /// Upper bound on the messages in any single model request, to check trimming.
+    pub max_request_messages: Option<usize>,
+    /// Substring of the last assistant message.
+    pub final_message: Option<String>,
+}
+
+/// Runs a scenario file and returns what did not match.
+pub fn run_scenario(path: &Path) -> Vec<String> {
+    let text = std::fs::read_to_string(path).expect("scenario file");
+    let scenario: Scenario = match serde_json::from_str(&text) {
+        Ok(scenario) => scenario,
+        Err(e) => return vec![format!("invalid scenario: {}", e)],
+    };
+    let mut replies = scenario.replies;
+    if let Some(cassette) = &scenario.cassette {
+        let cassette = path.parent().unwrap_or(Path::new(".")).join(cassette);
+        match aiosc::cassette::responses(&cassette.to_string_lossy()) {
+            Ok(responses) => replies.extend(responses),
+            Err(e) => return vec![format!("cassette: {}", e)],
+        }
+    }
+    let mut config = test_config(&scenario.config);
+    config.dry_run = scenario.dry_run;
+    let outcome = run_agent(&config, &scenario.prompt, &replies, scenario.results, &scenario.confirmations);
+
+    let mut failures = Vec::new();
+    let expect = scenario.expect;
+    let mut check = |what: &str, ok: bool, detail: String| {
+        if !ok {
+            failures.push(format!("{}: {}", what, detail));
+        }
+    };
+    if let Some(commands) = &expect.commands {
+        check("commands", &outcome.record.commands == commands, format!("ran {:?}", outcome.record.commands));
+    }
+    if let Some(confirmations) = &expect.confirmations {
+        check("confirmations", &outcome.record.confirmations == confirmations, format!("asked about {:?}", outcome.record.confirmations));
+    }
+    if let Some(state) = &expect.state {
+        let actual = match &outcome.state {
+            Ok(state) => state.name().to_string(),
+            Err(e) => format!("error ({})", e),
+        };
+        check("state", &actual == state, format!("ended {}", actual));
+    }
+    if let Some(exit_code) = expect.exit_code {
+        check("exit_code", outcome.exit_code() == exit_code, format!("exit code {}", outcome.exit_code()));
+    }
+    if let Some(results) = &expect.results {
+        let actual = &outcome.record.results;
+        let ok = actual.len() == results.len() && actual.iter().zip(results).all(|(a, e)| a.contains(e.as_str()));
+        check("results", ok, format!("got {:?}", actual));
+    }
+    if let Some(max) = expect.max_request_messages {
+        let largest = outcome.record.requests.iter().map(Vec::len).max().unwrap_or(0);
+        check("max_request_messages", largest <= max, format!("a request had {} messages", largest));
+    }
+    if let Some(text) = &expect.final_message {
+        let last = outcome.conversation.iter().rev().find(|m| m.role == "assistant").map(|m| m.content.clone()).unwrap_or_default();
+        check("final_message", last.contains(text.as_str()), format!("last assistant message was {:?}", last));
+    }
+    failures
+}